@@ -0,0 +1,117 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this file,
+ * You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+#![feature(slicing_syntax)]
+
+extern crate "rust-md2-collisions" as collisions;
+extern crate test;
+
+use collisions::{candidates, candidates_k, find_collisions_parallel, prefill_row};
+use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::Entry::{Occupied, Vacant};
+use std::os;
+use std::sync::TaskPool;
+use std::sync::mpsc::channel;
+use test::Bencher;
+
+// Every iteration checks all 2^16 candidates for k=2. Reporting those as
+// "bytes" makes the harness print throughput in candidates per second. This
+// goes for all benchmarks, whatever unit they count.
+//
+// Peak memory isn't reported. The harness has no hook to measure it per
+// benchmark, and the process' high water mark includes every benchmark that
+// ran before.
+static CANDIDATES: u64 = 256 * 256;
+
+#[bench]
+fn bench_hashmap(b: &mut Bencher) {
+  let state = prefill_row(14).unwrap();
+  b.bytes = CANDIDATES;
+
+  b.iter(|| {
+    let mut map = HashMap::with_capacity(256u * 256u);
+
+    for (cmp, msg) in candidates(state[], 14).unwrap() {
+      match map.entry(cmp) {
+        Vacant(entry) => { entry.set(vec!(msg)); }
+        Occupied(mut entry) => { entry.get_mut().push(msg); }
+      }
+    }
+
+    map.values().fold(0u, |count, msgs| count + msgs.len() - 1)
+  });
+}
+
+#[bench]
+fn bench_btreemap(b: &mut Bencher) {
+  let state = prefill_row(14).unwrap();
+  b.bytes = CANDIDATES;
+
+  b.iter(|| {
+    let mut map = BTreeMap::new();
+
+    // Every message replacing another one is a collision.
+    candidates(state[], 14).unwrap().filter(|&(ref cmp, ref msg)| {
+      map.insert(cmp.clone(), msg.clone()).is_some()
+    }).count()
+  });
+}
+
+#[bench]
+fn bench_sorted_vec(b: &mut Bencher) {
+  let state = prefill_row(14).unwrap();
+  b.bytes = CANDIDATES;
+
+  b.iter(|| {
+    let mut pairs: Vec<(Vec<u8>, Vec<u8>)> = candidates(state[], 14).unwrap().collect();
+    pairs.sort();
+
+    // Colliding messages end up next to each other.
+    pairs.windows(2).filter(|w| w[0].0 == w[1].0).count()
+  });
+}
+
+// Ships every candidate from the jobs to a single consumer, like test_k3.
+#[bench]
+fn bench_parallel_channel(b: &mut Bencher) {
+  let state = prefill_row(14).unwrap();
+  let pool = TaskPool::new(os::num_cpus());
+  b.bytes = CANDIDATES;
+
+  b.iter(|| {
+    let (tx, rx) = channel();
+
+    for byte in range(0u, 256) {
+      let txc = tx.clone();
+      let mut state = state.clone();
+
+      pool.execute(move || {
+        state[17] = byte as u8;
+        state[33] = byte as u8;
+
+        for candidate in candidates_k(state[], 14, 1).unwrap() {
+          if txc.send(candidate).is_err() {
+            panic!("sending failed");
+          }
+        }
+      });
+    }
+
+    let mut map = HashMap::with_capacity(256u * 256u);
+    for (cmp, msg) in rx.iter().take(256 * 256) {
+      match map.entry(cmp) {
+        Vacant(entry) => { entry.set(vec!(msg)); }
+        Occupied(mut entry) => { entry.get_mut().push(msg); }
+      }
+    }
+
+    map.values().fold(0u, |count, msgs| count + msgs.len() - 1)
+  });
+}
+
+#[bench]
+fn bench_parallel_sharded(b: &mut Bencher) {
+  b.bytes = CANDIDATES;
+  b.iter(|| find_collisions_parallel(2, os::num_cpus()).unwrap().len());
+}
@@ -0,0 +1,56 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this file,
+ * You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+#![feature(slicing_syntax)]
+
+extern crate "rust-md2-collisions" as collisions;
+extern crate test;
+
+use collisions::{candidates, find_collisions, prefill_row, IncrementalPrefill, StateMatrix};
+use test::Bencher;
+
+// Single rounds are covered by benches/compress.rs, the collision maps by
+// benches/collision_maps.rs. These measure the remaining stages of a search
+// end to end.
+
+#[bench]
+fn bench_prefill_row(b: &mut Bencher) {
+  b.iter(|| prefill_row(14));
+}
+
+#[bench]
+fn bench_incremental_prefill(b: &mut Bencher) {
+  b.iter(|| {
+    let mut prefill = IncrementalPrefill::new();
+
+    for _ in range(0u, 14) {
+      prefill.extend_by_one();
+    }
+
+    prefill.state().rows[14][1]
+  });
+}
+
+// Iterating all 2^16 candidates, for the prefilled state and a random one
+// to check that the state's contents don't affect the speed.
+#[bench]
+fn bench_candidates_prefilled(b: &mut Bencher) {
+  let state = prefill_row(14).unwrap();
+  b.bytes = 256 * 256;
+  b.iter(|| candidates(state[], 14).unwrap().count());
+}
+
+#[bench]
+fn bench_candidates_random(b: &mut Bencher) {
+  let state = StateMatrix::new_from_random(42).rows[14][1..].to_vec();
+  b.bytes = 256 * 256;
+  b.iter(|| candidates(state[], 14).unwrap().count());
+}
+
+#[bench]
+fn bench_find_collisions(b: &mut Bencher) {
+  let state = prefill_row(14).unwrap();
+  b.bytes = 256 * 256;
+  b.iter(|| find_collisions(candidates(state[], 14).unwrap()).unwrap().len());
+}
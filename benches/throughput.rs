@@ -0,0 +1,73 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this file,
+ * You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+#![feature(slicing_syntax)]
+
+extern crate "rust-md2-collisions" as collisions;
+extern crate test;
+
+use collisions::{compress_with_iv, StateMatrix};
+use std::os;
+use std::sync::{Arc, TaskPool};
+use std::sync::mpsc::channel;
+use test::Bencher;
+
+// Every iteration runs this many compressions per thread. Divide 2^32 by
+// the reported rate to estimate the duration of a k=4 search. Comparing
+// both benchmarks shows how well the machine scales to all of its CPUs.
+static COMPRESSIONS: uint = 4096;
+
+// Pseudorandom chaining values and messages, always the same.
+fn inputs() -> Vec<([u8; 16], [u8; 16])> {
+  let state = StateMatrix::new_from_random(42);
+
+  range(0u, COMPRESSIONS).map(|i| {
+    let row = state.rows[i % 19];
+    let mut iv = [0u8; 16];
+    let mut msg = [0u8; 16];
+
+    for j in range(0u, 16) {
+      iv[j] = row[1 + j] ^ (i as u8);
+      msg[j] = row[17 + j] ^ ((i >> 8) as u8);
+    }
+
+    (iv, msg)
+  }).collect()
+}
+
+fn compress_all(inputs: &[([u8; 16], [u8; 16])]) -> u8 {
+  inputs.iter().fold(0u8, |acc, &(ref iv, ref msg)| acc ^ compress_with_iv(iv, msg)[0])
+}
+
+#[bench]
+fn bench_single_thread(b: &mut Bencher) {
+  let inputs = inputs();
+  b.bytes = COMPRESSIONS as u64;
+  b.iter(|| compress_all(inputs[]));
+}
+
+#[bench]
+fn bench_all_cpus(b: &mut Bencher) {
+  let inputs = Arc::new(inputs());
+  let num_threads = os::num_cpus();
+  let pool = TaskPool::new(num_threads);
+  b.bytes = (COMPRESSIONS * num_threads) as u64;
+
+  b.iter(|| {
+    let (tx, rx) = channel();
+
+    for _ in range(0, num_threads) {
+      let txc = tx.clone();
+      let inputs = inputs.clone();
+
+      pool.execute(move || {
+        if txc.send(compress_all(inputs.as_slice())).is_err() {
+          panic!("sending failed");
+        }
+      });
+    }
+
+    rx.iter().take(num_threads).fold(0u8, |acc, result| acc ^ result)
+  });
+}
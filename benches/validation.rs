@@ -0,0 +1,44 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this file,
+ * You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+#![feature(slicing_syntax)]
+
+extern crate "rust-md2" as md2;
+extern crate "rust-md2-collisions" as collisions;
+extern crate test;
+
+use collisions::{candidates, prefill_row};
+use collisions::validation::validate_parallel;
+use std::collections::HashMap;
+use test::Bencher;
+
+// Validating the k=3 collisions means 2 * 32784 compressions. Validating
+// every k=2 candidate takes about as many, without having to run the much
+// slower k=3 search every time the harness calls a benchmark.
+fn candidate_map() -> HashMap<Vec<u8>,Vec<Vec<u8>>> {
+  let state = prefill_row(14).unwrap();
+  let mut map = HashMap::new();
+
+  for (cmp, msg) in candidates(state[], 14).unwrap() {
+    map.insert(cmp, vec!(msg));
+  }
+
+  map
+}
+
+#[bench]
+fn bench_validate_sequential(b: &mut Bencher) {
+  let map = candidate_map();
+  let empty = [0u8; 16];
+
+  b.iter(|| {
+    map.iter().all(|(cmp, msgs)| msgs.iter().all(|msg| md2::compress(&empty, msg[]) == *cmp))
+  });
+}
+
+#[bench]
+fn bench_validate_parallel(b: &mut Bencher) {
+  let map = candidate_map();
+  b.iter(|| validate_parallel(&map));
+}
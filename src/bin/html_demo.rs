@@ -0,0 +1,60 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this file,
+ * You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+#![feature(slicing_syntax)]
+
+extern crate "rust-md2-collisions" as collisions;
+
+use collisions::{candidates, prefill_row};
+use std::collections::HashMap;
+use std::io::File;
+use std::os;
+
+fn main() {
+  let args = os::args();
+  let path = if args.len() > 1 { args[1].clone() } else { "collision.html".to_string() };
+
+  let state = prefill_row(14).unwrap();
+  let mut seen = HashMap::with_capacity(256u * 256u);
+  let mut collision = None;
+
+  // Stop at the first message that compresses to a value seen before.
+  for (cmp, msg) in candidates(state[], 14).unwrap() {
+    match seen.get(&cmp) {
+      Some(other) => { collision = Some((cmp.clone(), msg.clone(), other.clone())); }
+      None => {}
+    }
+
+    if collision.is_some() {
+      break;
+    }
+
+    seen.insert(cmp, msg);
+  }
+
+  let (cmp, msg1, msg2) = collision.expect("no collision found");
+  let html = format!(include_str!("html_demo.html"),
+                     msg1 = hex_cells(msg1[], msg2[]),
+                     msg2 = hex_cells(msg2[], msg1[]),
+                     cmp = hex_cells(cmp[], cmp[]));
+
+  File::create(&Path::new(path[])).write_str(html[]).unwrap();
+  println!("Wrote {}.", path);
+}
+
+// Formats the given bytes as hex, highlighting those that differ from the
+// corresponding bytes in `other`.
+fn hex_cells(bytes: &[u8], other: &[u8]) -> String {
+  let mut html = String::new();
+
+  for (a, b) in bytes.iter().zip(other.iter()) {
+    if a == b {
+      html.push_str(format!("<td>{:02x}</td>", *a)[]);
+    } else {
+      html.push_str(format!("<td class=\"diff\">{:02x}</td>", *a)[]);
+    }
+  }
+
+  html
+}
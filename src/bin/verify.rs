@@ -0,0 +1,54 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this file,
+ * You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+#![feature(slicing_syntax)]
+
+extern crate "rust-md2-collisions" as collisions;
+
+use collisions::{to_hex, validate_collision};
+use collisions::export::import_hex;
+use std::io::{BufferedReader, stdin};
+use std::os;
+
+// Reads collisions in the format written by `export_hex`, e.g. from
+// `rust-md2-collisions --output hex`, and checks every one of them.
+fn main() {
+  let mut passed = 0u;
+  let mut failed = 0u;
+
+  println!("| Line | Result | Compressed |");
+  println!("|---|---|---|");
+
+  for (num, line) in BufferedReader::new(stdin()).lines().enumerate() {
+    let line = match line {
+      Ok(line) => line,
+      Err(err) => {
+        os::set_exit_status(1);
+        return println!("Reading stdin failed: {}", err);
+      }
+    };
+
+    // Collisions are parsed line by line to report errors where they are.
+    match import_hex(line[].trim_right_chars('\n')) {
+      Ok(parsed) => {
+        for collision in parsed.iter() {
+          let result = if validate_collision(collision) == Ok(true) { "PASS" } else { "FAIL" };
+          println!("| {} | {} | {} |", num + 1, result, to_hex(collision.cmp[]));
+
+          if result == "PASS" { passed += 1; } else { failed += 1; }
+        }
+      }
+      Err(err) => {
+        println!("| {} | FAIL | {} |", num + 1, err);
+        failed += 1;
+      }
+    }
+  }
+
+  println!("\n{} passed, {} failed.", passed, failed);
+
+  if failed > 0 {
+    os::set_exit_status(1);
+  }
+}
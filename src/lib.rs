@@ -5,24 +5,237 @@
 #![feature(slicing_syntax)]
 
 extern crate "rust-md2" as md2;
+extern crate libc;
+extern crate serialize;
 
+#[cfg(test)]
+extern crate quickcheck;
+
+use libc::{c_int, c_void, size_t};
 use md2::{SBOX, SBOXI};
+use serialize::json;
+use std::c_str::ToCStr;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry::{Occupied, Vacant};
+use std::fmt;
+use std::io::{BufferedReader, BufferedWriter, EndOfFile, File, InvalidInput, IoError};
+use std::io::{IoResult, TempDir};
+use std::io::stdio::stderr;
 use std::iter::{range_inclusive, repeat};
+use std::mem;
+use std::num::{Float, Int};
 use std::slice::bytes::{copy_memory, MutableByteVector};
+use std::sync::{Arc, Mutex, TaskPool};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
 
+#[derive(Clone)]
 struct ByteRange {
   current: Vec<u8>,
-  done: bool
+  done: bool,
+  end: Option<Vec<u8>>
 }
 
 impl ByteRange {
   fn new(num_bytes: uint) -> ByteRange {
-    ByteRange { current: repeat(0u8).take(num_bytes).collect(), done: false }
+    ByteRange { current: repeat(0u8).take(num_bytes).collect(), done: false, end: None }
+  }
+
+  // Starts enumerating at the given byte sequence.
+  fn from_start(start: &[u8]) -> ByteRange {
+    ByteRange { current: start.to_vec(), done: false, end: None }
+  }
+
+  // Stops enumerating before the given byte sequence, which must have the
+  // same length as the current one.
+  fn with_end(self, end: Vec<u8>) -> ByteRange {
+    assert_eq!(end.len(), self.current.len());
+    ByteRange { current: self.current, done: self.done, end: Some(end) }
+  }
+
+  // Splits the remaining byte sequences into `total_workers` contiguous
+  // segments and returns the one for the given worker.
+  fn partition(&self, total_workers: uint, worker_index: uint) -> ByteRange {
+    assert!(worker_index < total_workers);
+
+    let width = self.current.len();
+    let start = self.position();
+    let remaining = self.count_remaining();
+    let chunk = (remaining + total_workers as u64 - 1) / total_workers as u64;
+    let from = start + cmp::min(chunk * worker_index as u64, remaining);
+    let to = start + cmp::min(chunk * (worker_index as u64 + 1), remaining);
+
+    if from == to {
+      let zero = to_bytes(0, width);
+      return ByteRange::from_start(zero[]).with_end(zero);
+    }
+
+    let range = ByteRange::from_start(to_bytes(from, width)[]);
+
+    // The end of the last segment might not be representable.
+    match self.end {
+      None if to == start + remaining => range,
+      _ => range.with_end(to_bytes(to, width))
+    }
+  }
+
+  // The linear index of the next byte sequence, i.e. the current bytes
+  // interpreted as a big-endian integer. Supports up to 7 bytes.
+  pub fn position(&self) -> u64 {
+    self.current.iter().fold(0u64, |pos, &byte| (pos << 8) | byte as u64)
+  }
+
+  // The number of byte sequences of this width, regardless of the current
+  // position. Supports up to 7 bytes.
+  pub fn len(&self) -> u64 {
+    256u64.pow(self.current.len())
+  }
+
+  pub fn count_remaining(&self) -> u64 {
+    let end = match self.end {
+      Some(ref end) => end.iter().fold(0u64, |pos, &byte| (pos << 8) | byte as u64),
+      None => self.len()
+    };
+
+    if self.done { 0 } else { end - cmp::min(end, self.position()) }
+  }
+
+  // Tags every byte sequence with its linear index, e.g. to shard a search
+  // by having worker i only process indices where `index % n == i`.
+  #[allow(dead_code)]
+  pub fn indexed(self) -> IndexedByteRange {
+    IndexedByteRange { inner: self }
+  }
+}
+
+// Visits the same byte sequences as `ByteRange` but in a pseudorandom order
+// determined by a seed. A counter is mapped to byte sequences through a
+// balanced Feistel network over 8n bits. Every round is invertible, so the
+// whole network is a permutation and doesn't need to be kept in memory.
+pub struct ShuffledByteRange {
+  counter: u64,
+  num_bytes: uint,
+  keys: [u64; 4]
+}
+
+impl ShuffledByteRange {
+  // Supports up to 7 bytes.
+  pub fn new(num_bytes: uint, seed: u64) -> ShuffledByteRange {
+    assert!(num_bytes > 0 && num_bytes <= 7);
+
+    let mut rng = Xoshiro256::new(seed);
+    let mut keys = [0u64; 4];
+    for key in keys.iter_mut() {
+      *key = rng.next();
+    }
+
+    ShuffledByteRange { counter: 0, num_bytes: num_bytes, keys: keys }
+  }
+
+  fn permute(&self, position: u64) -> u64 {
+    let half_bits = 4 * self.num_bytes;
+    let mask = (1u64 << half_bits) - 1;
+    let mut left = position >> half_bits;
+    let mut right = position & mask;
+
+    for &key in self.keys.iter() {
+      // Any function works as round function, mix the bits a little.
+      let mut f = (right ^ key) * 0x9e3779b97f4a7c15;
+      f ^= f >> 29;
+
+      let next = left ^ (f & mask);
+      left = right;
+      right = next;
+    }
+
+    (left << half_bits) | right
+  }
+}
+
+impl Iterator<Vec<u8>> for ShuffledByteRange {
+  fn next(&mut self) -> Option<Vec<u8>> {
+    if self.counter == 256u64.pow(self.num_bytes) {
+      return None;
+    }
+
+    let position = self.permute(self.counter);
+    self.counter += 1;
+    Some(to_bytes(position, self.num_bytes))
+  }
+}
+
+pub struct IndexedByteRange {
+  inner: ByteRange
+}
+
+impl Iterator<(u64, Vec<u8>)> for IndexedByteRange {
+  fn next(&mut self) -> Option<(u64, Vec<u8>)> {
+    let index = self.inner.position();
+    self.inner.next().map(|bytes| (index, bytes))
+  }
+}
+
+// Enumerates all sequences of `n` symbols of the given alphabet, counting
+// in base `alphabet.len()`. Sequences are ordered by the positions of their
+// symbols in the alphabet.
+pub struct AlphabetRange {
+  alphabet: Vec<u8>,
+  current: Vec<uint>,
+  done: bool
+}
+
+impl AlphabetRange {
+  pub fn new(alphabet: &[u8], n: uint) -> AlphabetRange {
+    assert!(alphabet.len() > 0, "the alphabet must not be empty");
+    let current = repeat(0u).take(n).collect();
+    AlphabetRange { alphabet: alphabet.to_vec(), current: current, done: false }
+  }
+
+  // All byte values, i.e. the same sequences as `ByteRange::new(n)`.
+  pub fn full_byte(n: uint) -> AlphabetRange {
+    let alphabet: Vec<u8> = range(0u, 256).map(|b| b as u8).collect();
+    AlphabetRange::new(alphabet[], n)
+  }
+}
+
+impl Iterator<Vec<u8>> for AlphabetRange {
+  fn next(&mut self) -> Option<Vec<u8>> {
+    if self.done {
+      return None;
+    }
+
+    let bytes = self.current.iter().map(|&i| self.alphabet[i]).collect();
+
+    // Increase, carrying over to the left. Done once all digits wrapped.
+    self.done = true;
+    for i in range(0, self.current.len()).rev() {
+      self.current[i] += 1;
+
+      if self.current[i] < self.alphabet.len() {
+        self.done = false;
+        break;
+      }
+
+      self.current[i] = 0;
+    }
+
+    Some(bytes)
   }
 }
 
+// The last `width` bytes of the given integer, big-endian.
+fn to_bytes(position: u64, width: uint) -> Vec<u8> {
+  range(0, width).rev().map(|i| (position >> (8 * i)) as u8).collect()
+}
+
 impl Iterator<Vec<u8>> for ByteRange {
   fn next(&mut self) -> Option<Vec<u8>> {
+    match self.end {
+      Some(ref end) if self.current >= *end => { return None; }
+      _ => {}
+    }
+
     for i in range(0, self.current.len()).rev() {
       if self.current[i] == 255 {
         continue;
@@ -44,10 +257,77 @@ impl Iterator<Vec<u8>> for ByteRange {
   }
 }
 
+// The S-box of `AlgorithmVariant::IdentitySbox`.
+static IDENTITY_SBOX: [u8; 256] = [
+  0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+  16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31,
+  32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47,
+  48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63,
+  64, 65, 66, 67, 68, 69, 70, 71, 72, 73, 74, 75, 76, 77, 78, 79,
+  80, 81, 82, 83, 84, 85, 86, 87, 88, 89, 90, 91, 92, 93, 94, 95,
+  96, 97, 98, 99, 100, 101, 102, 103, 104, 105, 106, 107, 108, 109, 110, 111,
+  112, 113, 114, 115, 116, 117, 118, 119, 120, 121, 122, 123, 124, 125, 126, 127,
+  128, 129, 130, 131, 132, 133, 134, 135, 136, 137, 138, 139, 140, 141, 142, 143,
+  144, 145, 146, 147, 148, 149, 150, 151, 152, 153, 154, 155, 156, 157, 158, 159,
+  160, 161, 162, 163, 164, 165, 166, 167, 168, 169, 170, 171, 172, 173, 174, 175,
+  176, 177, 178, 179, 180, 181, 182, 183, 184, 185, 186, 187, 188, 189, 190, 191,
+  192, 193, 194, 195, 196, 197, 198, 199, 200, 201, 202, 203, 204, 205, 206, 207,
+  208, 209, 210, 211, 212, 213, 214, 215, 216, 217, 218, 219, 220, 221, 222, 223,
+  224, 225, 226, 227, 228, 229, 230, 231, 232, 233, 234, 235, 236, 237, 238, 239,
+  240, 241, 242, 243, 244, 245, 246, 247, 248, 249, 250, 251, 252, 253, 254, 255
+];
+
+// Weakened versions of MD2 to test the attack on. All variants but
+// `RandomSbox` use the MD2 S-box, all but `ReducedRounds` do 18 rounds.
+#[derive(Copy)]
+pub enum AlgorithmVariant {
+  FullMd2,
+  ReducedRounds(uint),
+  IdentitySbox,
+  RandomSbox([u8; 256])
+}
+
+impl AlgorithmVariant {
+  // Borrowed rather than copied, `compress` and `decompress` look the S-box
+  // up for every candidate.
+  fn sbox(&self) -> &[u8; 256] {
+    match *self {
+      AlgorithmVariant::IdentitySbox => &IDENTITY_SBOX,
+      AlgorithmVariant::RandomSbox(ref sbox) => sbox,
+      _ => &SBOX
+    }
+  }
+
+  fn sboxi(&self) -> [u8; 256] {
+    match *self {
+      AlgorithmVariant::FullMd2 | AlgorithmVariant::ReducedRounds(_) => SBOXI,
+      _ => {
+        let sbox = self.sbox();
+        let mut sboxi = [0u8; 256];
+
+        // The S-box must be a permutation for this to work.
+        for i in range(0u, 256) {
+          sboxi[sbox[i] as uint] = i as u8;
+        }
+
+        sboxi
+      }
+    }
+  }
+
+  fn rounds(&self) -> uint {
+    match *self {
+      AlgorithmVariant::ReducedRounds(rounds) => rounds,
+      _ => 18
+    }
+  }
+}
+
 pub struct Candidates {
   range: ByteRange,
   state: Vec<u8>,
-  row: uint
+  row: uint,
+  variant: AlgorithmVariant
 }
 
 impl Iterator<(Vec<u8>, Vec<u8>)> for Candidates {
@@ -61,179 +341,4619 @@ impl Iterator<(Vec<u8>, Vec<u8>)> for Candidates {
 
     // Set bytes for current candidate.
     let bytes = next.unwrap();
-    copy_memory(self.state.slice_mut(16, 18), bytes[]);
-    copy_memory(self.state.slice_mut(32, 34), bytes[]);
+    let width = bytes.len();
+    copy_memory(self.state.slice_mut(16, 16 + width), bytes[]);
+    copy_memory(self.state.slice_mut(32, 32 + width), bytes[]);
 
-    // Compute the final compression value.
-    let cmp = compress(self.state[], self.row);
+    // Compute the final compression value. Neither this nor `decompress`
+    // can fail, the state and row were checked on creation.
+    let cmp = compress(self.state[], self.row, &self.variant).unwrap();
 
     // Compute the original message leading to this state.
-    let msg = decompress(self.state[], self.row);
+    let msg = decompress(self.state[], self.row, &self.variant).unwrap();
 
     Some((cmp, msg))
   }
 }
 
-pub fn candidates(state: &[u8], row: uint) -> Candidates {
+pub fn candidates(state: &[u8], row: uint) -> Result<Candidates, Md2CollisionError> {
+  candidates_variant(state, row, AlgorithmVariant::FullMd2)
+}
+
+pub fn candidates_variant(state: &[u8], row: uint, variant: AlgorithmVariant) -> Result<Candidates, Md2CollisionError> {
+  try!(check_state(state));
+  try!(check_row(row));
+  try!(check_k(row, 2));
+
   // Test 2^16 combinations.
-  Candidates { range: ByteRange::new(2), state: state.to_vec(), row: row }
+  Ok(Candidates {
+    range: ByteRange::new(2),
+    state: state.to_vec(),
+    row: row,
+    variant: variant
+  })
 }
 
-pub fn prefill_row(num_rows: uint) -> Vec<u8> {
-  let mut state = [[0u8; 49]; 19];
+pub fn candidates_k(state: &[u8], row: uint, k: uint) -> Result<Candidates, Md2CollisionError> {
+  try!(check_state(state));
+  try!(check_row(row));
+  try!(check_k(row, k));
 
-  for row in range_inclusive(1, num_rows) {
-    // Fill row of T1.
-    for i in range(1, 17) {
-      state[row][i] = SBOX[state[row][i - 1] as uint] ^ state[row - 1][i];
-    }
+  // Test 2^(8k) combinations.
+  Ok(Candidates {
+    range: ByteRange::new(k),
+    state: state.to_vec(),
+    row: row,
+    variant: AlgorithmVariant::FullMd2
+  })
+}
 
-    // Last bytes are equal.
-    state[row][32] = state[row][16];
-    state[row][48] = state[row][16];
+#[derive(Copy, Show, PartialEq)]
+pub enum Md2CollisionError {
+  InvalidStateLength { expected: uint, got: uint },
+  InvalidRow { row: uint, max: uint },
+  InvalidKParameter(uint),
+  InconsistentState { row: uint }
+}
 
-    // Next t value.
-    state[row + 1][0] = state[row][48] + (row as u8) - 1;
+// At most 16 rows can be prefilled, that leaves no free bytes.
+static MAX_ROW: uint = 16;
+
+fn check_row(row: uint) -> Result<(), Md2CollisionError> {
+  if row > MAX_ROW {
+    return Err(Md2CollisionError::InvalidRow { row: row, max: MAX_ROW });
   }
 
-  // Compute triangles in T2 and T3.
-  for col in range(0, num_rows) {
-    for row in range_inclusive(2 + col, num_rows).rev() {
-      let xor = state[row][32 - col] ^ state[row - 1][32 - col];
+  Ok(())
+}
 
-      // We need the inverse S-box to compute triangles.
-      state[row][32 - col - 1] = SBOXI[xor as uint];
-      state[row][48 - col - 1] = SBOXI[xor as uint];
-    }
+// Fail early instead of indexing out of bounds in `compress`.
+fn check_state(state: &[u8]) -> Result<(), Md2CollisionError> {
+  if state.len() != 48 {
+    return Err(Md2CollisionError::InvalidStateLength { expected: 48, got: state.len() });
   }
 
-  // Return the desired row and throw away the first byte (t-values).
-  state[num_rows][1..].to_vec()
+  Ok(())
 }
 
-fn compress(state: &[u8], iteration: uint) -> Vec<u8> {
-  let mut t = state[47] + iteration as u8 - 1;
-  let mut x = state.to_vec();
+// The k free bytes must not overwrite any of the bytes prefilled for the
+// given row.
+fn check_k(row: uint, k: uint) -> Result<(), Md2CollisionError> {
+  if k == 0 || k > MAX_ROW - row {
+    return Err(Md2CollisionError::InvalidKParameter(k));
+  }
 
-  // Compute the MD2 compression function from the current state until we
-  // have the final compression state that would be fed into the next round.
-  for row in range(iteration, 18) {
-    for byte in x.iter_mut() {
-      *byte ^= SBOX[t as uint];
-      t = *byte;
+  Ok(())
+}
+
+// The number of rows to prefill for a search with k free bytes.
+fn row_for_k(k: uint) -> Result<uint, Md2CollisionError> {
+  if k == 0 || k > MAX_ROW {
+    return Err(Md2CollisionError::InvalidKParameter(k));
+  }
+
+  Ok(MAX_ROW - k)
+}
+
+// Lets functions doing I/O report invalid arguments through their `IoResult`.
+fn to_io_error(err: Md2CollisionError) -> IoError {
+  IoError { kind: InvalidInput, desc: "invalid arguments", detail: Some(err.to_string()) }
+}
+
+// Messages that all compress to the same value.
+#[derive(Clone, Show, PartialEq)]
+pub struct Collision {
+  pub cmp: Vec<u8>,
+  pub msgs: Vec<Vec<u8>>
+}
+
+// Groups the given pairs of compressed values and messages, both of which
+// must be 16 bytes long.
+pub fn find_collisions<I: Iterator<(Vec<u8>, Vec<u8>)>>(candidates: I) -> Result<Vec<Collision>, Md2CollisionError> {
+  let mut map = HashMap::new();
+
+  for (cmp, msg) in candidates {
+    for bytes in [cmp[], msg[]].iter() {
+      if bytes.len() != 16 {
+        return Err(Md2CollisionError::InvalidStateLength { expected: 16, got: bytes.len() });
+      }
     }
-    t += row as u8;
+
+    insert(&mut map, cmp, msg);
   }
 
-  x[..16].to_vec()
+  Ok(collisions_from_map(map))
 }
 
-fn decompress(state: &[u8], iteration: uint) -> Vec<u8> {
-  let mut x = state.to_vec();
+// Finds groups of at least `multiplicity` messages with k free bytes that
+// all compress to the same value.
+pub fn find_multi_collisions(state: &[u8], row: uint, k: uint,
+                             multiplicity: uint) -> Result<Vec<Collision>, Md2CollisionError> {
+  let collisions = try!(find_collisions(try!(candidates_k(state, row, k))));
+  Ok(collisions.into_iter().filter(|collision| collision.msgs.len() >= multiplicity).collect())
+}
 
-  // Compute the MD2 compression function from the current state backwards
-  // until we arrive at the original message that needs to be passed into it
-  // to result in the current state.
-  for row in range(0, iteration).rev() {
-    for col in range(1, 48).rev() {
-      x[col] ^= SBOX[x[col - 1] as uint];
+// Yields collisions as soon as they're found. Only the first message for
+// every compressed value is kept, each further message colliding with it is
+// yielded as a pair with the first one.
+pub struct CollisionIter {
+  inner: Candidates,
+  first: HashMap<Vec<u8>,Vec<u8>>
+}
+
+impl Iterator<Collision> for CollisionIter {
+  fn next(&mut self) -> Option<Collision> {
+    while let Some((cmp, msg)) = self.inner.next() {
+      match self.first.entry(cmp.clone()) {
+        Vacant(entry) => { entry.set(msg); }
+        Occupied(entry) => {
+          return Some(Collision { cmp: cmp, msgs: vec!(entry.get().clone(), msg) });
+        }
+      }
     }
 
-    let t = x[47] + (row as u8) - 1;
-    x[0] ^= SBOX[t as uint];
+    None
   }
+}
 
-  x[16..32].to_vec()
+pub fn collision_iter(state: &[u8], row: uint) -> Result<CollisionIter, Md2CollisionError> {
+  Ok(CollisionIter { inner: try!(candidates(state, row)), first: HashMap::new() })
 }
 
-#[cfg(test)]
-mod test {
-  use candidates;
-  use prefill_row;
+// Runs the search for k free bytes and writes every collision to `writer`
+// as soon as it's found, in the line format of `export::export_hex`. Each
+// further message colliding with a first one is written as a pair with it.
+// Returns the number of lines written. Only the first message for every
+// compressed value is kept in memory, nothing is buffered for `writer`.
+//
+// For k > 3 the candidates are computed 256 times, each pass only keeping
+// compressed values with one particular first byte. That holds at most
+// 2^24 messages at a time for k=4, instead of 2^32.
+pub fn find_collisions_streaming<W: Writer>(k: uint, writer: &mut W) -> IoResult<u64> {
+  stream_collisions(k, if k > 3 { 256 } else { 1 }, writer)
+}
 
-  use md2::compress;
-  use std::collections::HashMap;
-  use std::collections::hash_map::Entry::{Occupied, Vacant};
-  use std::sync::TaskPool;
-  use std::sync::mpsc::channel;
+// Like `find_collisions_streaming`, but splits the compressed values
+// between `num_passes` passes by their first byte.
+fn stream_collisions<W: Writer>(k: uint, num_passes: uint, writer: &mut W) -> IoResult<u64> {
+  let row = try!(row_for_k(k).map_err(to_io_error));
+  let state = try!(prefill_row(row).map_err(to_io_error));
+  let mut count = 0u64;
+
+  for pass in range(0, num_passes) {
+    let mut first = HashMap::new();
+
+    for (cmp, msg) in try!(candidates_k(state[], row, k).map_err(to_io_error)) {
+      if cmp[0] as uint % num_passes != pass {
+        continue;
+      }
 
-  // Insert the given candidate pair, consisting of the compressed and the
-  // original message, into the given hash map.
-  fn insert(map: &mut HashMap<Vec<u8>,Vec<Vec<u8>>>, cmp: Vec<u8>, msg: Vec<u8>) {
-    match map.entry(cmp) {
-      Vacant(entry) => { entry.set(vec!(msg)); }
-      Occupied(mut entry) => { entry.get_mut().push(msg); }
+      match first.entry(cmp.clone()) {
+        Vacant(entry) => { entry.set(msg); }
+        Occupied(entry) => {
+          let collision = Collision { cmp: cmp, msgs: vec!(entry.get().clone(), msg) };
+          try!(writer.write_str(export::export_hex(&[collision])[]));
+          count += 1;
+        }
+      }
     }
   }
 
-  // Validate all colliding entries in the given map to ensure that those
-  // messages do indeed collide when computing their compressed values.
-  fn validate(map: &HashMap<Vec<u8>,Vec<Vec<u8>>>) -> bool {
-    let empty = [0u8; 16];
+  Ok(count)
+}
 
-    // Ignore compressed values with only a single message (no collisions).
-    let collisions = map.iter().filter(|&(_, msgs)| msgs.len() > 1);
+// The positions of all bits that differ between two messages, where bit 0
+// is the least significant bit of the first byte.
+#[derive(PartialEq)]
+pub struct BitDiff {
+  pub bits: Vec<uint>
+}
 
-    collisions.all(|(cmp, msgs)| {
-      msgs.iter().all(|msg| compress(&empty, msg[]) == *cmp)
-    })
+// Formats the positions as e.g. "bits 8,9,24".
+impl fmt::Show for BitDiff {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let bits: Vec<String> = self.bits.iter().map(|bit| bit.to_string()).collect();
+    write!(f, "bits {}", bits.connect(","))
   }
+}
+
+pub fn bit_difference(a: &[u8], b: &[u8]) -> BitDiff {
+  let mut bits = vec!();
 
-  // Count the number of map entries that have more than a single message.
-  // Those will compress to the same final value and thus represent collisions.
-  fn count(map: &HashMap<Vec<u8>,Vec<Vec<u8>>>) -> uint {
-    map.values().fold(0u, |count, msgs| count + msgs.len() - 1)
+  for (i, (x, y)) in a.iter().zip(b.iter()).enumerate() {
+    for bit in range(0u, 8) {
+      if (*x ^ *y) & (1 << bit) > 0 {
+        bits.push(i * 8 + bit);
+      }
+    }
   }
 
-  #[test]
-  fn test_k2() {
-    let state = prefill_row(14);
+  BitDiff { bits: bits }
+}
 
-    // There will be ~2^16 entries (minus collisions).
-    let mut map = HashMap::with_capacity(256u * 256u);
+// Counts colliding pairs of messages by their number of differing bits.
+pub fn bit_difference_histogram(collisions: &[Collision]) -> HashMap<uint, u64> {
+  let mut histogram = HashMap::new();
 
-    // Iterate and record all candidate pairs.
-    for (cmp, msg) in candidates(state[], 14) {
-      insert(&mut map, cmp, msg);
+  for collision in collisions.iter() {
+    for (i, a) in collision.msgs.iter().enumerate() {
+      for b in collision.msgs[i + 1..].iter() {
+        match histogram.entry(bit_difference(a[], b[]).bits.len()) {
+          Vacant(entry) => { entry.set(1u64); }
+          Occupied(mut entry) => { *entry.get_mut() += 1; }
+        }
+      }
     }
+  }
 
-    assert!(validate(&map));
-    assert_eq!(count(&map), 141);
+  histogram
+}
+
+// Removes duplicate collisions regardless of the order of their messages,
+// e.g. after merging results registered by several processes. Messages are
+// left sorted and collisions ordered by their compressed values.
+pub fn dedup_collisions(collisions: &mut Vec<Collision>) {
+  for collision in collisions.iter_mut() {
+    collision.msgs.sort();
   }
 
-  #[test]
-  fn test_k3() {
-    let pool = TaskPool::new(8u);
-    let (tx, rx) = channel();
-    let state = prefill_row(13);
+  collisions.sort_by(|a, b| (&a.cmp, &a.msgs).cmp(&(&b.cmp, &b.msgs)));
+  collisions.dedup();
+}
 
-    for byte in range(0u, 256u) {
-      let txc = tx.clone();
-      let mut state = state.clone();
+// Messages as nodes, with an edge labeled with the compressed value between
+// every two messages of the same collision. As a message always compresses
+// to the same value, every connected component is one collision unless the
+// given collisions overlap.
+pub struct CollisionGraph {
+  nodes: Vec<Vec<u8>>,
+  edges: Vec<Vec<(uint, Vec<u8>)>>
+}
 
-      pool.execute(move || {
-        // Set the third bytes of T2 and T3.
-        state[18] = byte as u8;
-        state[34] = byte as u8;
+impl CollisionGraph {
+  pub fn from_collisions(collisions: &[Collision]) -> CollisionGraph {
+    let mut graph = CollisionGraph { nodes: vec!(), edges: vec!() };
+    let mut indices = HashMap::new();
 
-        for candidate in candidates(state[], 13) {
-          if txc.send(candidate).is_err() {
-            panic!("sending failed");
+    for collision in collisions.iter() {
+      let ids: Vec<uint> = collision.msgs.iter().map(|msg| {
+        match indices.entry(msg.clone()) {
+          Occupied(entry) => *entry.get(),
+          Vacant(entry) => {
+            graph.nodes.push(msg.clone());
+            graph.edges.push(vec!());
+            *entry.set(graph.nodes.len() - 1)
           }
         }
-      });
+      }).collect();
+
+      for (i, &a) in ids.iter().enumerate() {
+        for &b in ids[i + 1..].iter() {
+          if a != b && !graph.edges[a].iter().any(|&(node, _)| node == b) {
+            graph.edges[a].push((b, collision.cmp.clone()));
+            graph.edges[b].push((a, collision.cmp.clone()));
+          }
+        }
+      }
     }
 
-    // There will be ~2^24 entries (minus collisions).
-    let total = 256u * 256u * 256u;
-    let mut map = HashMap::with_capacity(total);
+    graph
+  }
 
-    // Merge partial results.
-    for (key, msg) in rx.iter().take(total) {
-      insert(&mut map, key, msg);
+  pub fn num_nodes(&self) -> uint {
+    self.nodes.len()
+  }
+
+  pub fn num_edges(&self) -> uint {
+    self.edges.iter().fold(0u, |count, edges| count + edges.len()) / 2
+  }
+
+  // The messages along the simple path with the most hops. Finding it is
+  // NP-hard in general, but components are tiny here.
+  pub fn longest_chain(&self) -> Vec<Vec<u8>> {
+    let mut visited: Vec<bool> = repeat(false).take(self.nodes.len()).collect();
+    let mut path: Vec<uint> = vec!();
+    let mut longest = vec!();
+
+    for start in range(0, self.nodes.len()) {
+      self.extend_chain(start, &mut visited, &mut path, &mut longest);
     }
 
-    assert!(validate(&map));
-    assert_eq!(count(&map), 32784);
+    longest.iter().map(|&node| self.nodes[node].clone()).collect()
+  }
+
+  fn extend_chain(&self, node: uint, visited: &mut Vec<bool>, path: &mut Vec<uint>,
+                  longest: &mut Vec<uint>) {
+    visited[node] = true;
+    path.push(node);
+
+    if path.len() > longest.len() {
+      *longest = path.clone();
+    }
+
+    for &(next, _) in self.edges[node].iter() {
+      if !visited[next] {
+        self.extend_chain(next, visited, path, longest);
+      }
+    }
+
+    path.pop();
+    visited[node] = false;
+  }
+}
+
+// Finds the colliding pair of messages that differ in the fewest bits and
+// returns it with the number of differing bits.
+pub fn find_lowest_hamming_collision(state: &[u8], row: uint) -> Result<Option<(Vec<u8>, Vec<u8>, u32)>, Md2CollisionError> {
+  let mut lowest: Option<(Vec<u8>, Vec<u8>, u32)> = None;
+
+  for collision in try!(find_collisions(try!(candidates(state, row)))).iter() {
+    for (i, a) in collision.msgs.iter().enumerate() {
+      for b in collision.msgs[i + 1..].iter() {
+        let distance = a.iter().zip(b.iter()).fold(0u32, |distance, (x, y)| {
+          distance + (*x ^ *y).count_ones() as u32
+        });
+
+        match lowest {
+          Some((_, _, lowest)) if lowest <= distance => {}
+          _ => { lowest = Some((a.clone(), b.clone(), distance)); }
+        }
+      }
+    }
+  }
+
+  Ok(lowest)
+}
+
+// Searches the candidates for a message compressing to the given value.
+pub fn find_second_preimage(state: &[u8], row: uint, target: &[u8]) -> Result<Option<Vec<u8>>, Md2CollisionError> {
+  let mut candidates = try!(candidates(state, row));
+  Ok(candidates.find(|&(ref cmp, _)| cmp[] == target).map(|(_, msg)| msg))
+}
+
+fn collisions_from_map(map: HashMap<Vec<u8>,Vec<Vec<u8>>>) -> Vec<Collision> {
+  // Ignore compressed values with only a single message (no collisions).
+  let mut collisions: Vec<Collision> = map.into_iter()
+    .filter(|&(_, ref msgs)| msgs.len() > 1)
+    .map(|(cmp, msgs)| Collision { cmp: cmp, msgs: msgs })
+    .collect();
+
+  collisions.sort_by(|a, b| a.cmp.cmp(&b.cmp));
+  collisions
+}
+
+// Runs the k=3 search with the candidates split into `num_threads` chunks.
+pub fn k3_search_sharded(num_threads: uint) -> Vec<Collision> {
+  search_sharded(3, num_threads)
+}
+
+// Runs the search for the given k with the candidates split into
+// `num_threads` contiguous chunks. Every job fills its own map, those are
+// merged at the end so that only a single message per job has to go through
+// the channel.
+fn search_sharded(k: uint, num_threads: uint) -> Vec<Collision> {
+  let pool = TaskPool::new(num_threads);
+  let rx = spawn_shards(&pool, k, num_threads);
+
+  // Merge partial results.
+  let mut merged = HashMap::new();
+  for map in rx.iter().take(num_threads) {
+    for (cmp, msgs) in map.into_iter() {
+      for msg in msgs.into_iter() {
+        insert(&mut merged, cmp.clone(), msg);
+      }
+    }
+  }
+
+  collisions_from_map(merged)
+}
+
+// Starts `num_threads` jobs on the given pool, each searching one of as many
+// contiguous chunks of the candidates for k free bytes. Every job sends its
+// map through the returned channel when it's done.
+fn spawn_shards(pool: &TaskPool, k: uint, num_threads: uint) -> Receiver<HashMap<Vec<u8>,Vec<Vec<u8>>>> {
+  // Partitioning needs linear indices, which only fit for up to 7 bytes.
+  assert!(k > 0 && k <= 7);
+
+  let (tx, rx) = channel();
+  let row = 16 - k;
+  let state = prefill_row(row).unwrap();
+  let bytes = ByteRange::new(k);
+
+  for worker in range(0, num_threads) {
+    let txc = tx.clone();
+    let candidates = Candidates {
+      range: bytes.partition(num_threads, worker),
+      state: state.clone(),
+      row: row,
+      variant: AlgorithmVariant::FullMd2
+    };
+
+    pool.execute(move || {
+      let mut map = HashMap::new();
+
+      for (cmp, msg) in candidates {
+        insert(&mut map, cmp, msg);
+      }
+
+      if txc.send(map).is_err() {
+        panic!("sending failed");
+      }
+    });
+  }
+
+  rx
+}
+
+// Configures and runs a search for collisions with k free bytes, taking care
+// of prefilling the state. Results aren't validated, callers can check them
+// with `validate_collision`.
+pub struct CollisionSearchBuilder<F> {
+  k: uint,
+  threads: uint,
+  on_collision: F
+}
+
+impl CollisionSearchBuilder<fn(&Collision)> {
+  pub fn new() -> CollisionSearchBuilder<fn(&Collision)> {
+    CollisionSearchBuilder { k: 2, threads: 1, on_collision: ignore_collision as fn(&Collision) }
+  }
+}
+
+impl<F: Fn(&Collision)> CollisionSearchBuilder<F> {
+  // Checked by `run`.
+  pub fn k(mut self, k: uint) -> CollisionSearchBuilder<F> {
+    self.k = k;
+    self
+  }
+
+  pub fn threads(mut self, threads: uint) -> CollisionSearchBuilder<F> {
+    assert!(threads > 0);
+    self.threads = threads;
+    self
+  }
+
+  // The given function is called with every collision found.
+  pub fn on_collision<G: Fn(&Collision)>(self, on_collision: G) -> CollisionSearchBuilder<G> {
+    CollisionSearchBuilder { k: self.k, threads: self.threads, on_collision: on_collision }
+  }
+
+  pub fn run(&self) -> Result<Vec<Collision>, Md2CollisionError> {
+    let row = try!(row_for_k(self.k));
+
+    // Sharding can only partition candidates of up to 7 bytes.
+    let collisions = if self.threads > 1 && self.k <= 7 {
+      search_sharded(self.k, self.threads)
+    } else {
+      let state = try!(prefill_row(row));
+      try!(find_collisions(try!(candidates_k(state[], row, self.k))))
+    };
+
+    for collision in collisions.iter() {
+      (self.on_collision)(collision);
+    }
+
+    Ok(collisions)
+  }
+}
+
+fn ignore_collision(_: &Collision) {}
+
+// Like `search_sharded`, but jobs insert their candidates directly into one
+// of 256 maps, chosen by the first byte of the compressed value, instead of
+// building full maps that are merged at the end. Every map has its own lock
+// so jobs rarely wait for each other.
+pub fn find_collisions_parallel(k: uint, num_threads: uint) -> Result<Vec<Collision>, Md2CollisionError> {
+  // Every job fixes one of the free bytes.
+  if k < 2 {
+    return Err(Md2CollisionError::InvalidKParameter(k));
+  }
+
+  let pool = TaskPool::new(num_threads);
+  let (tx, rx) = channel();
+  let row = try!(row_for_k(k));
+  let state = try!(prefill_row(row));
+  let shards: Arc<Vec<Mutex<HashMap<Vec<u8>,Vec<Vec<u8>>>>>> =
+    Arc::new(range(0u, 256).map(|_| Mutex::new(HashMap::new())).collect());
+
+  for byte in range(0u, 256) {
+    let txc = tx.clone();
+    let shards = shards.clone();
+    let mut state = state.clone();
+
+    pool.execute(move || {
+      // Set the last free bytes of T2 and T3.
+      state[15 + k] = byte as u8;
+      state[31 + k] = byte as u8;
+
+      for (cmp, msg) in candidates_k(state[], row, k - 1).unwrap() {
+        let mut shard = (*shards)[cmp[0] as uint].lock().unwrap();
+        insert(&mut *shard, cmp, msg);
+      }
+
+      // Only signal that the job is done.
+      if txc.send(()).is_err() {
+        panic!("sending failed");
+      }
+    });
+  }
+
+  for _ in rx.iter().take(256) {}
+
+  let mut collisions = vec!();
+  for shard in shards.iter() {
+    let map = mem::replace(&mut *shard.lock().unwrap(), HashMap::new());
+    collisions.extend(collisions_from_map(map).into_iter());
+  }
+
+  collisions.sort_by(|a, b| a.cmp.cmp(&b.cmp));
+  Ok(collisions)
+}
+
+// Runs the search for the given k with `num_threads` jobs and calls `f` with
+// every colliding pair of messages as soon as the job finding its second
+// message is done, instead of waiting for the whole search. A compressed
+// value with more than two messages is reported once for every further
+// message, paired with the first one. `f` is only called from the calling
+// thread. Returns when the search is done.
+pub fn run_parallel_with_callback<F>(k: uint, num_threads: uint, mut f: F) -> Result<(), Md2CollisionError>
+    where F: FnMut(Collision) {
+  // Sharding can only partition candidates of up to 7 bytes.
+  if k == 0 || k > 7 {
+    return Err(Md2CollisionError::InvalidKParameter(k));
+  }
+
+  let pool = TaskPool::new(num_threads);
+  let rx = spawn_shards(&pool, k, num_threads);
+  let mut first: HashMap<Vec<u8>,Vec<u8>> = HashMap::new();
+
+  // Merge partial results as they come in.
+  for map in rx.iter().take(num_threads) {
+    for (cmp, msgs) in map.into_iter() {
+      for msg in msgs.into_iter() {
+        match first.entry(cmp.clone()) {
+          Vacant(entry) => { entry.set(msg); }
+          Occupied(entry) => {
+            f(Collision { cmp: cmp.clone(), msgs: vec!(entry.get().clone(), msg) });
+          }
+        }
+      }
+    }
+  }
+
+  Ok(())
+}
+
+// Runs the search for k = 16 - row free bytes using at most about
+// `ram_limit_bytes` for candidates. Whenever that is exceeded the candidates
+// are sorted and spilled to a file in a private directory inside `tmp_dir`,
+// at the end all files are merged to find collisions. The directory is
+// removed again when the search is done, even if it fails. Every candidate
+// takes 32 bytes, the k=3 search needs 512 MiB without a limit. Messages of
+// a collision are sorted.
+pub fn find_collisions_memory_limited(state: &[u8], row: uint, ram_limit_bytes: uint,
+                                      tmp_dir: &Path) -> IoResult<Vec<Collision>> {
+  try!(check_state(state).and(check_row(row)).map_err(to_io_error));
+
+  let dir = try!(TempDir::new_in(tmp_dir, "md2-chunks"));
+  let max_records = cmp::max(1, ram_limit_bytes / 32);
+  let mut buffer: Vec<[u8; 32]> = vec!();
+  let mut chunks = vec!();
+
+  for (cmp, msg) in try!(candidates_k(state, row, 16 - row).map_err(to_io_error)) {
+    let mut record = [0u8; 32];
+    copy_memory(record.slice_mut(0, 16), cmp[]);
+    copy_memory(record.slice_mut(16, 32), msg[]);
+    buffer.push(record);
+
+    if buffer.len() == max_records {
+      let path = dir.path().join(format!("chunk_{}.bin", chunks.len()));
+      try!(spill(&mut buffer, &path));
+      chunks.push(path);
+    }
+  }
+
+  if buffer.len() > 0 {
+    let path = dir.path().join(format!("chunk_{}.bin", chunks.len()));
+    try!(spill(&mut buffer, &path));
+    chunks.push(path);
+  }
+
+  let mut readers = vec!();
+  let mut heads = vec!();
+
+  for path in chunks.iter() {
+    let mut reader = BufferedReader::new(try!(File::open(path)));
+    heads.push(try!(read_record(&mut reader)));
+    readers.push(reader);
+  }
+
+  let mut collisions = vec!();
+  let mut group: Option<Collision> = None;
+
+  // Merge all sorted chunks, equal compressed values are now adjacent.
+  loop {
+    let next = heads.iter().enumerate().filter_map(|(i, head)| {
+      match *head { Some(record) => Some((record, i)), None => None }
+    }).min();
+
+    let (record, i) = match next {
+      Some(next) => next,
+      None => break
+    };
+
+    heads[i] = try!(read_record(&mut readers[i]));
+
+    let (cmp, msg) = (record[..16].to_vec(), record[16..].to_vec());
+    let same = match group { Some(ref group) => group.cmp == cmp, None => false };
+
+    if same {
+      group.as_mut().unwrap().msgs.push(msg);
+      continue;
+    }
+
+    if let Some(group) = group.take() {
+      if group.msgs.len() > 1 {
+        collisions.push(group);
+      }
+    }
+
+    group = Some(Collision { cmp: cmp, msgs: vec!(msg) });
+  }
+
+  if let Some(group) = group.take() {
+    if group.msgs.len() > 1 {
+      collisions.push(group);
+    }
+  }
+
+  Ok(collisions)
+}
+
+// Sorts the buffered candidates and writes them to the given file.
+fn spill(buffer: &mut Vec<[u8; 32]>, path: &Path) -> IoResult<()> {
+  buffer.sort();
+
+  let mut writer = BufferedWriter::new(try!(File::create(path)));
+  for record in buffer.iter() {
+    try!(writer.write(record[]));
+  }
+
+  buffer.clear();
+  writer.flush()
+}
+
+fn read_record<R: Reader>(reader: &mut R) -> IoResult<Option<[u8; 32]>> {
+  match reader.read_exact(32) {
+    Ok(bytes) => {
+      let mut record = [0u8; 32];
+      copy_memory(&mut record, bytes[]);
+      Ok(Some(record))
+    }
+    Err(ref err) if err.kind == EndOfFile => Ok(None),
+    Err(err) => Err(err)
+  }
+}
+
+// Finds partial collisions, i.e. messages whose compressed values agree on
+// all bits set in `output_mask`. The compressed value of each collision is
+// reported with all other bits cleared.
+pub fn find_collisions_masked(state: &[u8], row: uint, output_mask: &[u8; 16]) -> Result<Vec<Collision>, Md2CollisionError> {
+  find_collisions(try!(candidates(state, row)).map(|(cmp, msg)| {
+    let masked = cmp.iter().zip(output_mask.iter()).map(|(a, b)| *a & *b).collect();
+    (masked, msg)
+  }))
+}
+
+// Groups all k=2 candidates by the first `prefix_len` bytes of their
+// compressed values only. With 2^16 candidates there are about
+// 2^31 / 2^(8 * prefix_len) collisions for a random function.
+pub fn find_partial_collisions(state: &[u8], row: uint,
+                               prefix_len: uint) -> Result<HashMap<Vec<u8>,Vec<Vec<u8>>>, Md2CollisionError> {
+  assert!(prefix_len <= 16, "prefix must be at most 16 bytes, got {}", prefix_len);
+  let mut map = HashMap::new();
+
+  for (cmp, msg) in try!(candidates(state, row)) {
+    insert(&mut map, cmp[..prefix_len].to_vec(), msg);
+  }
+
+  Ok(map)
+}
+
+// Finds collisions with less memory by first folding all compressed values
+// into 16-bit indices and tracking those in bitsets. Folding causes false
+// positives, for k=2 more than 24000 candidates share an index with another
+// one while only 141 actually collide. A second pass thus runs a full search
+// restricted to the candidates with a duplicate index.
+pub fn find_collisions_bitset(state: &[u8], row: uint) -> Result<Vec<Collision>, Md2CollisionError> {
+  let mut seen = [0u64; 1024];
+  let mut dups = [0u64; 1024];
+
+  for (cmp, _) in try!(candidates(state, row)) {
+    let index = fold(cmp[]);
+
+    if seen[index / 64] & (1 << (index % 64)) > 0 {
+      dups[index / 64] |= 1 << (index % 64);
+    }
+
+    seen[index / 64] |= 1 << (index % 64);
+  }
+
+  find_collisions(try!(candidates(state, row)).filter(|&(ref cmp, _)| {
+    let index = fold(cmp[]);
+    dups[index / 64] & (1 << (index % 64)) > 0
+  }))
+}
+
+// XOR-folds the given bytes into a 16-bit index.
+fn fold(bytes: &[u8]) -> uint {
+  bytes.chunks(2).fold(0u, |index, pair| index ^ ((pair[0] as uint) << 8 | pair[1] as uint))
+}
+
+// Accumulates collisions across processes in a shared file, one collision per
+// line: the compressed value followed by all messages, hex-encoded and
+// separated by spaces. Writers hold an exclusive advisory lock on the file
+// while appending a record, so records of concurrent writers never
+// interleave. Readers should only run once all writers are done.
+pub struct GlobalCollisionRegistry {
+  path: Path
+}
+
+impl GlobalCollisionRegistry {
+  pub fn new(path: Path) -> GlobalCollisionRegistry {
+    GlobalCollisionRegistry { path: path }
+  }
+
+  pub fn register_collision(&self, collision: &Collision) -> IoResult<()> {
+    let mut record = to_hex(collision.cmp[]);
+
+    for msg in collision.msgs.iter() {
+      record.push(' ');
+      record.push_str(to_hex(msg[])[]);
+    }
+
+    record.push('\n');
+    append_locked(&self.path, record.as_bytes())
+  }
+
+  pub fn all_collisions(&self) -> IoResult<Vec<Collision>> {
+    let mut reader = BufferedReader::new(try!(File::open(&self.path)));
+    let mut collisions = vec!();
+
+    for line in reader.lines() {
+      let line = try!(line);
+      let fields: Option<Vec<Vec<u8>>> = line[].words().map(from_hex).collect();
+
+      match fields {
+        Some(ref fields) if fields.len() > 2 => {
+          collisions.push(Collision { cmp: fields[0].clone(), msgs: fields[1..].to_vec() });
+        }
+        _ => {
+          return Err(IoError {
+            kind: InvalidInput,
+            desc: "malformed collision record",
+            detail: Some(line.clone())
+          });
+        }
+      }
+    }
+
+    Ok(collisions)
+  }
+}
+
+extern {
+  fn flock(fd: c_int, operation: c_int) -> c_int;
+}
+
+static LOCK_EX: c_int = 2;
+static LOCK_UN: c_int = 8;
+
+// Appends the given bytes to a file while holding an exclusive `flock()` on
+// it. `write()` might not write everything at once, hence the lock.
+fn append_locked(path: &Path, bytes: &[u8]) -> IoResult<()> {
+  let fd = path.with_c_str(|path| unsafe {
+    libc::open(path, libc::O_WRONLY | libc::O_APPEND | libc::O_CREAT, 0o644)
+  });
+
+  if fd < 0 {
+    return Err(IoError::last_error());
+  }
+
+  let result = unsafe { write_locked(fd, bytes) };
+  unsafe { libc::close(fd); }
+  result
+}
+
+unsafe fn write_locked(fd: c_int, bytes: &[u8]) -> IoResult<()> {
+  if flock(fd, LOCK_EX) != 0 {
+    return Err(IoError::last_error());
+  }
+
+  let mut written = 0u;
+
+  while written < bytes.len() {
+    let rest = bytes[written..];
+    let num_bytes = libc::write(fd, rest.as_ptr() as *const c_void, rest.len() as size_t);
+
+    if num_bytes < 0 {
+      let err = IoError::last_error();
+      flock(fd, LOCK_UN);
+      return Err(err);
+    }
+
+    written += num_bytes as uint;
+  }
+
+  flock(fd, LOCK_UN);
+  Ok(())
+}
+
+// A collision together with the k it was found with, so that it can be
+// checkpointed to disk and validated or analyzed by another process.
+#[derive(Clone, Show, PartialEq, Encodable, Decodable)]
+pub struct CollisionRecord {
+  pub cmp: Vec<u8>,
+  pub msgs: Vec<Vec<u8>>,
+  pub k: uint
+}
+
+// Writes the given records to a file as a JSON array.
+pub fn write_collisions(path: &Path, records: &[CollisionRecord]) -> IoResult<()> {
+  let mut file = try!(File::create(path));
+  file.write_str(json::encode(&records)[])
+}
+
+pub fn read_collisions(path: &Path) -> IoResult<Vec<CollisionRecord>> {
+  let s = try!(File::open(path).read_to_string());
+
+  json::decode(s[]).map_err(|err| IoError {
+    kind: InvalidInput,
+    desc: "malformed collision records",
+    detail: Some(err.to_string())
+  })
+}
+
+// A snapshot of a running search that can be saved to disk and resumed by
+// another process. The file holds the row, the position of the byte range
+// and all candidates found so far:
+//
+//   row, done, k: u8
+//   current: k bytes
+//   has end: u8, followed by k bytes if set
+//   number of compressed values: u32
+//   per compressed value: 16 bytes, number of messages: u32, 16 bytes each
+//
+// All integers are big-endian.
+pub struct SearchState {
+  range: ByteRange,
+  map: HashMap<Vec<u8>,Vec<Vec<u8>>>,
+  row: uint
+}
+
+impl SearchState {
+  pub fn new(row: uint, k: uint) -> Result<SearchState, Md2CollisionError> {
+    try!(check_row(row));
+    try!(check_k(row, k));
+    Ok(SearchState { range: ByteRange::new(k), map: HashMap::new(), row: row })
+  }
+
+  // The candidates not yet tried by this search. The row was checked when
+  // creating or loading the search.
+  pub fn candidates(&self) -> Candidates {
+    Candidates {
+      range: self.range.clone(),
+      state: prefill_row(self.row).unwrap(),
+      row: self.row,
+      variant: AlgorithmVariant::FullMd2
+    }
+  }
+
+  // Tries up to `n` more candidates, yields whether there are any left.
+  pub fn advance(&mut self, n: uint) -> bool {
+    let mut candidates = self.candidates();
+
+    for (cmp, msg) in candidates.by_ref().take(n) {
+      insert(&mut self.map, cmp, msg);
+    }
+
+    self.range = candidates.range;
+    self.range.count_remaining() > 0
+  }
+
+  pub fn collisions(&self) -> Vec<Collision> {
+    collisions_from_map(self.map.clone())
+  }
+
+  pub fn save(&self, path: &Path) -> IoResult<()> {
+    let mut file = try!(File::create(path));
+
+    try!(file.write_u8(self.row as u8));
+    try!(write_byte_range(&mut file, &self.range));
+    try!(file.write_be_u32(self.map.len() as u32));
+
+    for (cmp, msgs) in self.map.iter() {
+      try!(file.write(cmp[]));
+      try!(file.write_be_u32(msgs.len() as u32));
+
+      for msg in msgs.iter() {
+        try!(file.write(msg[]));
+      }
+    }
+
+    Ok(())
+  }
+
+  pub fn load(path: &Path) -> IoResult<SearchState> {
+    let mut file = try!(File::open(path));
+
+    let row = try!(file.read_u8()) as uint;
+    let range = try!(read_byte_range(&mut file));
+
+    if row > MAX_ROW || check_k(row, range.current.len()).is_err() {
+      return Err(IoError {
+        kind: InvalidInput,
+        desc: "malformed search state",
+        detail: Some(format!("row = {}, k = {}", row, range.current.len()))
+      });
+    }
+    let mut map = HashMap::new();
+
+    for _ in range(0, try!(file.read_be_u32())) {
+      let cmp = try!(file.read_exact(16));
+      let num_msgs = try!(file.read_be_u32()) as uint;
+      let mut msgs = Vec::with_capacity(num_msgs);
+
+      for _ in range(0, num_msgs) {
+        msgs.push(try!(file.read_exact(16)));
+      }
+
+      map.insert(cmp, msgs);
+    }
+
+    Ok(SearchState { range: range, map: map, row: row })
+  }
+}
+
+// Wraps a `ByteRange` and saves its position to the given file every
+// `checkpoint_every` byte sequences, using the same format as the range in
+// `SearchState`. An existing checkpoint is picked up on creation so that
+// an interrupted enumeration continues where it was last saved. If saving
+// fails the error is yielded instead of the byte sequence, a restart then
+// continues from the last checkpoint that was saved.
+pub struct CheckpointedByteRange {
+  inner: ByteRange,
+  checkpoint_path: Path,
+  checkpoint_every: uint,
+  count: uint
+}
+
+impl CheckpointedByteRange {
+  pub fn new(num_bytes: uint, checkpoint_path: Path, checkpoint_every: uint) -> IoResult<CheckpointedByteRange> {
+    assert!(checkpoint_every > 0);
+
+    let inner = if checkpoint_path.exists() {
+      try!(read_byte_range(&mut try!(File::open(&checkpoint_path))))
+    } else {
+      ByteRange::new(num_bytes)
+    };
+
+    // Don't resume enumerating sequences of a different length.
+    if inner.current.len() != num_bytes {
+      return Err(IoError {
+        kind: InvalidInput,
+        desc: "checkpoint has a different number of bytes",
+        detail: Some(format!("expected {}, got {}", num_bytes, inner.current.len()))
+      });
+    }
+
+    Ok(CheckpointedByteRange {
+      inner: inner,
+      checkpoint_path: checkpoint_path,
+      checkpoint_every: checkpoint_every,
+      count: 0
+    })
+  }
+
+  pub fn save_checkpoint(&self) -> IoResult<()> {
+    write_byte_range(&mut try!(File::create(&self.checkpoint_path)), &self.inner)
+  }
+}
+
+impl Iterator<IoResult<Vec<u8>>> for CheckpointedByteRange {
+  fn next(&mut self) -> Option<IoResult<Vec<u8>>> {
+    let next = self.inner.next();
+
+    if next.is_some() {
+      self.count += 1;
+
+      if self.count % self.checkpoint_every == 0 {
+        if let Err(err) = self.save_checkpoint() {
+          return Some(Err(err));
+        }
+      }
+    }
+
+    next.map(|bytes| Ok(bytes))
+  }
+}
+
+fn write_byte_range(writer: &mut Writer, range: &ByteRange) -> IoResult<()> {
+  try!(writer.write_u8(range.done as u8));
+  try!(writer.write_u8(range.current.len() as u8));
+  try!(writer.write(range.current[]));
+
+  match range.end {
+    Some(ref end) => {
+      try!(writer.write_u8(1));
+      writer.write(end[])
+    }
+    None => writer.write_u8(0)
+  }
+}
+
+fn read_byte_range(reader: &mut Reader) -> IoResult<ByteRange> {
+  let done = try!(reader.read_u8()) > 0;
+  let k = try!(reader.read_u8()) as uint;
+
+  if k == 0 || k > 16 {
+    return Err(IoError {
+      kind: InvalidInput,
+      desc: "malformed byte range",
+      detail: Some(format!("k = {}", k))
+    });
+  }
+
+  let current = try!(reader.read_exact(k));
+  let end = if try!(reader.read_u8()) > 0 { Some(try!(reader.read_exact(k))) } else { None };
+  Ok(ByteRange { current: current, done: done, end: end })
+}
+
+#[derive(Show, PartialEq)]
+pub enum ValidationError {
+  WrongMessageLength { got: uint }
+}
+
+// Checks that all messages of the given collision compress to the same value
+// when starting from the all-zero chaining value. Every message is checked
+// and compared in constant time, no matter which of them is invalid.
+pub fn validate_collision(collision: &Collision) -> Result<bool, ValidationError> {
+  let empty = [0u8; 16];
+
+  // MD2 operates on 16-byte blocks only.
+  for msg in collision.msgs.iter() {
+    if msg.len() != 16 {
+      return Err(ValidationError::WrongMessageLength { got: msg.len() });
+    }
+  }
+
+  Ok(collision.msgs.iter().fold(true, |valid, msg| {
+    valid & util::constant_time_eq(md2::compress(&empty, msg[])[], collision.cmp[])
+  }))
+}
+
+// Searches for collisions for every k from 1 to `max_k`, trying at most
+// `max_candidates` candidates for each k to bound the runtime.
+pub fn find_all_k_up_to(max_k: uint, max_candidates: uint) -> Result<Vec<(uint, Vec<Collision>)>, Md2CollisionError> {
+  let mut results = vec!();
+
+  for k in range_inclusive(1, max_k) {
+    let row = try!(row_for_k(k));
+    let state = try!(prefill_row(row));
+    let candidates = try!(candidates_k(state[], row, k));
+    results.push((k, try!(find_collisions(candidates.take(max_candidates)))));
+  }
+
+  Ok(results)
+}
+
+// Wraps `Candidates` and calls the given logger with every pair of the
+// compressed value and the original message before yielding it.
+pub struct VerboseCandidates<L> {
+  inner: Candidates,
+  logger: L
+}
+
+impl<L: Fn(&[u8], &[u8])> VerboseCandidates<L> {
+  pub fn new(inner: Candidates, logger: L) -> VerboseCandidates<L> {
+    VerboseCandidates { inner: inner, logger: logger }
+  }
+}
+
+impl VerboseCandidates<fn(&[u8], &[u8])> {
+  pub fn stderr(inner: Candidates) -> VerboseCandidates<fn(&[u8], &[u8])> {
+    VerboseCandidates::new(inner, log_stderr as fn(&[u8], &[u8]))
+  }
+
+  pub fn null(inner: Candidates) -> VerboseCandidates<fn(&[u8], &[u8])> {
+    VerboseCandidates::new(inner, log_null as fn(&[u8], &[u8]))
+  }
+}
+
+impl<L: Fn(&[u8], &[u8])> Iterator<(Vec<u8>, Vec<u8>)> for VerboseCandidates<L> {
+  fn next(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
+    let next = self.inner.next();
+
+    if let Some((ref cmp, ref msg)) = next {
+      (self.logger)(cmp[], msg[]);
+    }
+
+    next
+  }
+}
+
+fn log_stderr(cmp: &[u8], msg: &[u8]) {
+  let _ = writeln!(&mut stderr(), "{} {}", to_hex(cmp), to_hex(msg));
+}
+
+fn log_null(_: &[u8], _: &[u8]) {}
+
+// Wraps `Candidates` and calls the given callback with the number of
+// candidates yielded so far after every batch of them, 65536 by default.
+pub struct CandidatesWithProgress<F> {
+  inner: Candidates,
+  callback: F,
+  batch: u64,
+  count: u64
+}
+
+impl<F: FnMut(u64)> CandidatesWithProgress<F> {
+  pub fn every(self, batch: u64) -> CandidatesWithProgress<F> {
+    assert!(batch > 0);
+    CandidatesWithProgress {
+      inner: self.inner,
+      callback: self.callback,
+      batch: batch,
+      count: self.count
+    }
+  }
+}
+
+impl<F: FnMut(u64)> Iterator<(Vec<u8>, Vec<u8>)> for CandidatesWithProgress<F> {
+  fn next(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
+    let next = self.inner.next();
+
+    if next.is_some() {
+      self.count += 1;
+
+      if self.count % self.batch == 0 {
+        (self.callback)(self.count);
+      }
+    }
+
+    next
+  }
+}
+
+pub fn candidates_with_progress<F>(state: &[u8], row: uint, callback: F)
+    -> Result<CandidatesWithProgress<F>, Md2CollisionError> where F: FnMut(u64) {
+  let inner = try!(candidates(state, row));
+  Ok(CandidatesWithProgress { inner: inner, callback: callback, batch: 65536, count: 0 })
+}
+
+// Wraps `Candidates` and skips all candidates whose compressed value was
+// yielded before, i.e. enumerates the image of the compression function.
+pub struct CandidatesDeduplicated {
+  inner: Candidates,
+  seen: HashSet<Vec<u8>>
+}
+
+impl CandidatesDeduplicated {
+  pub fn new(inner: Candidates) -> CandidatesDeduplicated {
+    CandidatesDeduplicated { inner: inner, seen: HashSet::new() }
+  }
+}
+
+impl Iterator<(Vec<u8>, Vec<u8>)> for CandidatesDeduplicated {
+  fn next(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
+    while let Some((cmp, msg)) = self.inner.next() {
+      if self.seen.insert(cmp.clone()) {
+        return Some((cmp, msg));
+      }
+    }
+
+    None
+  }
+}
+
+// Wraps `Candidates` and skips all candidates whose message contains any of
+// the blocked byte patterns.
+pub struct CandidatesWithoutPatterns {
+  inner: Candidates,
+  blocklist: Vec<Vec<u8>>
+}
+
+impl Iterator<(Vec<u8>, Vec<u8>)> for CandidatesWithoutPatterns {
+  fn next(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
+    while let Some((cmp, msg)) = self.inner.next() {
+      let blocked = self.blocklist.iter().any(|pattern| {
+        pattern.is_empty() || msg.windows(pattern.len()).any(|window| window == pattern[])
+      });
+
+      if !blocked {
+        return Some((cmp, msg));
+      }
+    }
+
+    None
+  }
+}
+
+// Wraps `Candidates` and only yields candidates whose message satisfies the
+// given predicate.
+pub struct CandidatesFiltered<F> {
+  inner: Candidates,
+  predicate: F
+}
+
+impl<F: Fn(&[u8]) -> bool> Iterator<(Vec<u8>, Vec<u8>)> for CandidatesFiltered<F> {
+  fn next(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
+    while let Some((cmp, msg)) = self.inner.next() {
+      if (self.predicate)(msg[]) {
+        return Some((cmp, msg));
+      }
+    }
+
+    None
+  }
+}
+
+pub fn candidates_filtered<F: Fn(&[u8]) -> bool>(state: &[u8], row: uint, f: F) -> Result<CandidatesFiltered<F>, Md2CollisionError> {
+  Ok(CandidatesFiltered { inner: try!(candidates(state, row)), predicate: f })
+}
+
+pub fn candidates_without_patterns(state: &[u8], row: uint, blocklist: &[Vec<u8>]) -> Result<CandidatesWithoutPatterns, Md2CollisionError> {
+  Ok(CandidatesWithoutPatterns { inner: try!(candidates(state, row)), blocklist: blocklist.to_vec() })
+}
+
+#[derive(Clone)]
+pub struct SearchStatistics {
+  pub candidates_evaluated: u64,
+  pub collisions_found: u64,
+  pub elapsed: Duration,
+  pub bytes_enumerated: uint
+}
+
+impl SearchStatistics {
+  pub fn collision_rate(&self) -> f64 {
+    if self.candidates_evaluated == 0 {
+      return 0f64;
+    }
+
+    self.collisions_found as f64 / self.candidates_evaluated as f64
+  }
+}
+
+// A handle to statistics updated by `TrackedCandidates`, can be cloned and
+// sent to another thread to monitor a running search.
+#[derive(Clone)]
+pub struct SharedStatistics(Arc<Mutex<SearchStatistics>>);
+
+impl SharedStatistics {
+  pub fn snapshot(&self) -> SearchStatistics {
+    let SharedStatistics(ref stats) = *self;
+    stats.lock().unwrap().clone()
+  }
+}
+
+// Wraps `Candidates` and updates the shared statistics with every candidate.
+// A candidate counts as a collision if its compressed value was seen before.
+pub struct TrackedCandidates {
+  inner: Candidates,
+  seen: HashSet<Vec<u8>>,
+  stats: SharedStatistics
+}
+
+impl TrackedCandidates {
+  pub fn new(inner: Candidates) -> TrackedCandidates {
+    let stats = SearchStatistics {
+      candidates_evaluated: 0,
+      collisions_found: 0,
+      elapsed: Duration::zero(),
+      bytes_enumerated: 0
+    };
+
+    TrackedCandidates {
+      inner: inner,
+      seen: HashSet::new(),
+      stats: SharedStatistics(Arc::new(Mutex::new(stats)))
+    }
+  }
+
+  pub fn statistics(&self) -> SharedStatistics {
+    self.stats.clone()
+  }
+}
+
+impl Iterator<(Vec<u8>, Vec<u8>)> for TrackedCandidates {
+  fn next(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut next = None;
+    let elapsed = Duration::span(|| { next = self.inner.next(); });
+    let width = self.inner.range.current.len();
+
+    if let Some((ref cmp, _)) = next {
+      let collision = !self.seen.insert(cmp.clone());
+
+      let SharedStatistics(ref stats) = self.stats;
+      let mut stats = stats.lock().unwrap();
+      stats.candidates_evaluated += 1;
+      stats.collisions_found += collision as u64;
+      stats.elapsed = stats.elapsed + elapsed;
+      stats.bytes_enumerated += width;
+    }
+
+    next
+  }
+}
+
+// How often every S-box entry was looked up.
+#[derive(Copy)]
+pub struct SboxCoverageMap {
+  pub counts: [u64; 256]
+}
+
+impl SboxCoverageMap {
+  pub fn new() -> SboxCoverageMap {
+    SboxCoverageMap { counts: [0u64; 256] }
+  }
+
+  pub fn record_lookup(&mut self, index: u8) {
+    self.counts[index as uint] += 1;
+  }
+
+  // The fraction of S-box entries that were looked up at least once.
+  pub fn coverage_fraction(&self) -> f64 {
+    let hit = self.counts.iter().filter(|&&count| count > 0).count();
+    hit as f64 / 256f64
+  }
+}
+
+// Computes the same values as `compress` and records all S-box lookups in
+// a coverage map that can be shared between threads.
+pub struct CoverageTrackingCompressor {
+  coverage: Arc<Mutex<SboxCoverageMap>>
+}
+
+impl CoverageTrackingCompressor {
+  pub fn new(coverage: Arc<Mutex<SboxCoverageMap>>) -> CoverageTrackingCompressor {
+    CoverageTrackingCompressor { coverage: coverage }
+  }
+
+  pub fn compress(&self, state: &[u8], row: uint) -> Vec<u8> {
+    let mut local = SboxCoverageMap::new();
+    let mut t = state[47] + row as u8 - 1;
+    let mut x = state.to_vec();
+
+    for i in range(row, 18) {
+      for byte in x.iter_mut() {
+        local.record_lookup(t);
+        *byte ^= SBOX[t as uint];
+        t = *byte;
+      }
+      t += i as u8;
+    }
+
+    // Take the lock only once per call.
+    let mut coverage = self.coverage.lock().unwrap();
+    for (total, &count) in coverage.counts.iter_mut().zip(local.counts.iter()) {
+      *total += count;
+    }
+
+    x[..16].to_vec()
+  }
+}
+
+// A compact signature of a candidate, the first four bytes of its compressed
+// value XOR'ed with the first four bytes of its message.
+#[derive(Copy, PartialEq, Eq, Hash)]
+pub struct CandidateSignature(pub u32);
+
+impl CandidateSignature {
+  pub fn new(cmp: &[u8], msg: &[u8]) -> CandidateSignature {
+    CandidateSignature(range(0u, 4).fold(0u32, |sig, i| {
+      (sig << 8) | (cmp[i] ^ msg[i]) as u32
+    }))
+  }
+}
+
+// Approximate set of candidates for deduplication across search runs. There
+// are no false negatives, but with n signatures stored a query for a new
+// candidate is a false positive with a probability of about n / 2^32.
+pub struct CandidateSet {
+  signatures: HashSet<CandidateSignature>
+}
+
+impl CandidateSet {
+  pub fn new() -> CandidateSet {
+    CandidateSet { signatures: HashSet::new() }
+  }
+
+  // Returns false if the candidate (probably) was inserted before.
+  pub fn insert(&mut self, cmp: &[u8], msg: &[u8]) -> bool {
+    self.signatures.insert(CandidateSignature::new(cmp, msg))
+  }
+
+  pub fn contains(&self, cmp: &[u8], msg: &[u8]) -> bool {
+    self.signatures.contains(&CandidateSignature::new(cmp, msg))
+  }
+
+  pub fn len(&self) -> uint {
+    self.signatures.len()
+  }
+}
+
+pub fn prefill_row(num_rows: uint) -> Result<Vec<u8>, Md2CollisionError> {
+  try!(check_row(num_rows));
+  Ok(prefill_row_variant(num_rows, &AlgorithmVariant::FullMd2))
+}
+
+// The magic "MD2C" followed by the version of the cache format.
+static PREFILL_CACHE_HEADER: &'static [u8] = b"MD2C\x01";
+
+// Reads the result of `prefill_row` from `prefill_{num_rows}.bin` in the
+// given directory, computing and storing it first if there's no such file
+// with the current magic and version.
+pub fn prefill_row_cached(num_rows: uint, cache_dir: &Path) -> IoResult<Vec<u8>> {
+  let path = cache_dir.join(format!("prefill_{}.bin", num_rows));
+
+  if path.exists() {
+    let cached = try!(File::open(&path).read_to_end());
+
+    let header_len = PREFILL_CACHE_HEADER.len();
+
+    if cached.len() == header_len + 48 && cached[..header_len] == PREFILL_CACHE_HEADER[] {
+      return Ok(cached[header_len..].to_vec());
+    }
+  }
+
+  let row = try!(prefill_row(num_rows).map_err(to_io_error));
+  let mut file = try!(File::create(&path));
+  try!(file.write(PREFILL_CACHE_HEADER));
+  try!(file.write(row[]));
+  Ok(row)
+}
+
+// Checks that repeated calls to `prefill_row` return the same bytes.
+pub fn prefill_row_verify(num_rows: uint) -> bool {
+  prefill_row(num_rows) == prefill_row(num_rows)
+}
+
+// Determines k for a state returned by `prefill_row`, every number of
+// prefilled rows yields a different state.
+pub fn infer_k_from_state(state: &[u8]) -> Option<uint> {
+  range(0u, 16).find(|&num_rows| {
+    prefill_row(num_rows).unwrap()[] == state
+  }).map(|num_rows| 16 - num_rows)
+}
+
+// The 48 bytes passed around as the state of a single row, split into its
+// three blocks T1, T2, and T3.
+#[derive(Clone, Copy, PartialEq, Show)]
+pub struct Md2State {
+  pub t1: [u8; 16],
+  pub t2: [u8; 16],
+  pub t3: [u8; 16]
+}
+
+impl Md2State {
+  pub fn from_bytes(bytes: &[u8; 48]) -> Md2State {
+    let mut state = Md2State { t1: [0u8; 16], t2: [0u8; 16], t3: [0u8; 16] };
+    copy_memory(&mut state.t1, bytes[..16]);
+    copy_memory(&mut state.t2, bytes[16..32]);
+    copy_memory(&mut state.t3, bytes[32..]);
+    state
+  }
+
+  // Like `from_bytes`, but for slices of any length, e.g. from `prefill_row`.
+  pub fn from_slice(bytes: &[u8]) -> Option<Md2State> {
+    if bytes.len() != 48 {
+      return None;
+    }
+
+    let mut array = [0u8; 48];
+    copy_memory(&mut array, bytes);
+    Some(Md2State::from_bytes(&array))
+  }
+
+  pub fn as_bytes(&self) -> [u8; 48] {
+    let mut bytes = [0u8; 48];
+    copy_memory(bytes.slice_mut(0, 16), &self.t1);
+    copy_memory(bytes.slice_mut(16, 32), &self.t2);
+    copy_memory(bytes.slice_mut(32, 48), &self.t3);
+    bytes
+  }
+}
+
+// The full state matrix, each of the 19 rows holds the t-value followed
+// by the 48 bytes of T1, T2, and T3.
+#[derive(Copy)]
+pub struct StateMatrix {
+  pub rows: [[u8; 49]; 19]
+}
+
+impl StateMatrix {
+  pub fn new_zeroed() -> StateMatrix {
+    StateMatrix { rows: [[0u8; 49]; 19] }
+  }
+
+  // Fills the matrix with pseudorandom bytes, the same seed always yields
+  // the same matrix.
+  pub fn new_from_random(seed: u64) -> StateMatrix {
+    let mut rng = Xoshiro256::new(seed);
+    let mut state = StateMatrix::new_zeroed();
+
+    for row in state.rows.iter_mut() {
+      for byte in row.iter_mut() {
+        *byte = (rng.next() >> 56) as u8;
+      }
+    }
+
+    state
+  }
+
+  pub fn row_xor(&self, row_a: uint, row_b: uint) -> [u8; 49] {
+    xor_rows(&self.rows[row_a], &self.rows[row_b])
+  }
+
+  // Checks that T2 and T3 are equal in all rows, as they are after
+  // `prefill_row`. Yields the row and column, relative to T2, of the first
+  // mismatch.
+  pub fn assert_t2_equals_t3(&self) -> Result<(), (uint, uint)> {
+    for (num, row) in self.rows.iter().enumerate() {
+      for col in range(0u, 16) {
+        if row[17 + col] != row[33 + col] {
+          return Err((num, col));
+        }
+      }
+    }
+
+    Ok(())
+  }
+
+  // The total number of bits that differ between both matrices.
+  pub fn hamming_distance(&self, other: &StateMatrix) -> u32 {
+    range(0, 19).fold(0u32, |distance, row| {
+      xor_rows(&self.rows[row], &other.rows[row]).iter().fold(distance, |distance, byte| {
+        distance + byte.count_ones() as u32
+      })
+    })
+  }
+
+  // The differences between adjacent rows, these are the S-box outputs
+  // applied in each round.
+  pub fn consecutive_row_xors(&self) -> Vec<[u8; 49]> {
+    range(0, 18).map(|row| self.row_xor(row + 1, row)).collect()
+  }
+}
+
+// Renders the matrix as a grid of hex bytes, one line per row prefixed with
+// its number, and the t-values, T1, T2, and T3 separated by bars.
+impl fmt::Show for StateMatrix {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    try!(writeln!(f, "   |  t | {:47} | {:47} | {:47}", "T1", "T2", "T3"));
+
+    for (num, row) in self.rows.iter().enumerate() {
+      try!(writeln!(f, "{:2} | {:02X} | {} | {} | {}", num, row[0], to_spaced_hex(row[1..17]),
+                    to_spaced_hex(row[17..33]), to_spaced_hex(row[33..49])));
+    }
+
+    Ok(())
+  }
+}
+
+// How a cell of a matrix built by `prefill_state` was determined.
+#[derive(Copy, PartialEq)]
+enum PrefillCell {
+  // Set by `candidates`, i.e. the k free bytes.
+  Free,
+  // T1 filled row by row through the S-box, and the t values following.
+  Sbox,
+  // T2 and T3 filled backwards through the inverse S-box.
+  Triangle,
+  Unconstrained
+}
+
+fn prefill_cell(num_rows: uint, row: uint, col: uint) -> PrefillCell {
+  let k = 16 - cmp::min(num_rows, 16);
+
+  if row == num_rows && ((col > 16 && col <= 16 + k) || (col > 32 && col <= 32 + k)) {
+    PrefillCell::Free
+  } else if row > 0 && row <= num_rows && ((col > 0 && col <= 16) || col == 32 || col == 48) {
+    PrefillCell::Sbox
+  } else if col == 0 && row >= 2 && row <= num_rows + 1 {
+    // The t values follow from the row above.
+    PrefillCell::Sbox
+  } else if (col >= 33 - num_rows && col <= 31) || (col >= 49 - num_rows && col <= 47) {
+    // Cell [row][31 - c] is filled for rows 2 + c to `num_rows`.
+    let c = 31 - if col > 32 { col - 16 } else { col };
+    if row >= 2 + c && row <= num_rows { PrefillCell::Triangle } else { PrefillCell::Unconstrained }
+  } else {
+    PrefillCell::Unconstrained
+  }
+}
+
+impl StateMatrix {
+  // Like the `Show` output, but marks how every cell of a matrix prefilled
+  // with `num_rows` rows was determined. Free cells are yellow, S-box cells
+  // green, and triangle cells cyan. Without colors, cells are followed by
+  // ".", "S", and "T", respectively.
+  pub fn pretty_print(&self, num_rows: uint, color: bool) -> String {
+    let header: Vec<String> = range(0u, 49).map(|col| format!("{:02} ", col)).collect();
+    let mut out = format!("   | {}\n", join_blocks(header[]));
+
+    for (num, row) in self.rows.iter().enumerate() {
+      let cells: Vec<String> = range(0u, 49).map(|col| {
+        let kind = prefill_cell(num_rows, num, col);
+
+        if color {
+          let code = match kind {
+            PrefillCell::Free => 33u,
+            PrefillCell::Sbox => 32u,
+            PrefillCell::Triangle => 36u,
+            PrefillCell::Unconstrained => 0u
+          };
+
+          format!("\x1b[{}m{:02X}\x1b[0m ", code, row[col])
+        } else {
+          let marker = match kind {
+            PrefillCell::Free => '.',
+            PrefillCell::Sbox => 'S',
+            PrefillCell::Triangle => 'T',
+            PrefillCell::Unconstrained => ' '
+          };
+
+          format!("{:02X}{}", row[col], marker)
+        }
+      }).collect();
+
+      out.push_str(format!("{:2} | {}\n", num, join_blocks(cells[]))[]);
+    }
+
+    out
+  }
+}
+
+// Joins the cells of a row, separating t, T1, T2, and T3 by vertical bars.
+fn join_blocks(cells: &[String]) -> String {
+  format!("{}| {}| {}| {}", cells[..1].concat(), cells[1..17].concat(),
+          cells[17..33].concat(), cells[33..49].concat())
+}
+
+// A state matrix for `prefill_row` that computes each cell only when first
+// accessed, following the same rules as `prefill_state`. Supports up to 16
+// prefilled rows.
+pub struct LazyStateMatrix {
+  num_rows: uint,
+  cache: [[Option<u8>; 49]; 19]
+}
+
+impl LazyStateMatrix {
+  pub fn new(num_rows: uint) -> LazyStateMatrix {
+    assert!(num_rows <= 16);
+    LazyStateMatrix { num_rows: num_rows, cache: [[None; 49]; 19] }
+  }
+
+  pub fn get(&mut self, row: uint, col: uint) -> u8 {
+    if let Some(byte) = self.cache[row][col] {
+      return byte;
+    }
+
+    let byte = self.compute(row, col);
+    self.cache[row][col] = Some(byte);
+    byte
+  }
+
+  fn compute(&mut self, row: uint, col: uint) -> u8 {
+    let filled = row >= 1 && row <= self.num_rows;
+
+    match col {
+      // The t-value is derived from the last byte of the previous row.
+      0 if row >= 2 && row <= self.num_rows + 1 => {
+        self.get(row - 1, 48) + (row as u8) - 2
+      }
+
+      // Fill row of T1.
+      1...16 if filled => {
+        SBOX[self.get(row, col - 1) as uint] ^ self.get(row - 1, col)
+      }
+
+      // Last bytes are equal.
+      32 | 48 if filled => self.get(row, 16),
+
+      // The triangle in T2 needs the inverse S-box.
+      17...31 if 31 - col < self.num_rows && row >= 33 - col && filled => {
+        SBOXI[(self.get(row, col + 1) ^ self.get(row - 1, col + 1)) as uint]
+      }
+
+      // The triangle in T3 equals the one in T2.
+      33...47 => self.get(row, col - 16),
+
+      _ => 0
+    }
+  }
+}
+
+// Computes the same state as `prefill_state`, one row at a time. Each new
+// row only adds its T1 bytes and one more cell to every triangle, so all
+// rows up to 16 cost no more than computing the last one.
+pub struct IncrementalPrefill {
+  state: StateMatrix,
+  current_rows: uint
+}
+
+impl IncrementalPrefill {
+  pub fn new() -> IncrementalPrefill {
+    IncrementalPrefill { state: StateMatrix::new_zeroed(), current_rows: 0 }
+  }
+
+  pub fn state(&self) -> &StateMatrix {
+    &self.state
+  }
+
+  // Returns the same bytes as `prefill_row` for the new number of rows.
+  pub fn extend_by_one(&mut self) -> &[u8] {
+    assert!(self.current_rows < 16);
+    self.current_rows += 1;
+
+    let row = self.current_rows;
+    self.fill_row(row);
+    RowView(&self.state.rows[row]).blocks()
+  }
+
+  fn fill_row(&mut self, row: uint) {
+    let state = &mut self.state.rows;
+    fill_t1(state, row, &SBOX);
+
+    // Extend triangles in T2 and T3 by the new row.
+    for col in range(0, row - 1) {
+      fill_triangles(state, row, col, &SBOXI);
+    }
+  }
+}
+
+// A row of the state matrix, the t-value followed by T1, T2, and T3.
+struct RowView<'a>(&'a [u8; 49]);
+
+impl<'a> RowView<'a> {
+  fn t_value(&self) -> u8 {
+    self.0[0]
+  }
+
+  fn t1(&self) -> &'a [u8] {
+    self.0[1..17]
+  }
+
+  fn t2(&self) -> &'a [u8] {
+    self.0[17..33]
+  }
+
+  // T1, T2, and T3 without the t-value, as passed to `candidates`.
+  fn blocks(&self) -> &'a [u8] {
+    self.0[1..]
+  }
+}
+
+// Like `RowView`, but allows changing the row.
+struct Row<'a>(&'a mut [u8; 49]);
+
+impl<'a> Row<'a> {
+  fn view(&self) -> RowView {
+    RowView(&*self.0)
+  }
+
+  fn set_t_value(&mut self, t: u8) {
+    self.0[0] = t;
+  }
+
+  fn t1_mut(&mut self) -> &mut [u8] {
+    self.0.slice_mut(1, 17)
+  }
+
+  fn t2_mut(&mut self) -> &mut [u8] {
+    self.0.slice_mut(17, 33)
+  }
+
+  fn t3_mut(&mut self) -> &mut [u8] {
+    self.0.slice_mut(33, 49)
+  }
+}
+
+// The given row of the state matrix and the one above it, which is only
+// read when filling the row.
+fn split_rows<'a>(state: &'a mut [[u8; 49]; 19], row: uint) -> (RowView<'a>, Row<'a>) {
+  let (above, below) = state.split_at_mut(row);
+  (RowView(&above[row - 1]), Row(&mut below[0]))
+}
+
+// Fills T1 of the given row from the row above, copies its last byte to T2
+// and T3, and sets the t-value of the next row.
+fn fill_t1(state: &mut [[u8; 49]; 19], row: uint, sbox: &[u8; 256]) {
+  let last = {
+    let (above, mut current) = split_rows(state, row);
+    let mut x = current.view().t_value();
+
+    for (byte, &prev) in current.t1_mut().iter_mut().zip(above.t1().iter()) {
+      x = sbox[x as uint] ^ prev;
+      *byte = x;
+    }
+
+    // Last bytes are equal.
+    current.t2_mut()[15] = x;
+    current.t3_mut()[15] = x;
+    x
+  };
+
+  // Next t value.
+  Row(&mut state[row + 1]).set_t_value(last + (row as u8) - 1);
+}
+
+// Computes the cell of the triangles in T2 and T3 that is `col` bytes left
+// of the last one in the given row, from the cell to its right and the one
+// above that.
+fn fill_triangles(state: &mut [[u8; 49]; 19], row: uint, col: uint, sboxi: &[u8; 256]) {
+  let (above, mut current) = split_rows(state, row);
+  let xor = current.view().t2()[15 - col] ^ above.t2()[15 - col];
+
+  // We need the inverse S-box to compute triangles.
+  current.t2_mut()[14 - col] = sboxi[xor as uint];
+  current.t3_mut()[14 - col] = sboxi[xor as uint];
+}
+
+fn xor_rows(a: &[u8; 49], b: &[u8; 49]) -> [u8; 49] {
+  let mut xor = [0u8; 49];
+
+  for col in range(0, 49) {
+    xor[col] = a[col] ^ b[col];
+  }
+
+  xor
+}
+
+// The xoshiro256** generator, seeded with splitmix64.
+struct Xoshiro256 {
+  s: [u64; 4]
+}
+
+impl Xoshiro256 {
+  fn new(seed: u64) -> Xoshiro256 {
+    let mut seed = seed;
+    let mut s = [0u64; 4];
+
+    for word in s.iter_mut() {
+      seed += 0x9e3779b97f4a7c15;
+      let mut z = seed;
+      z = (z ^ (z >> 30)) * 0xbf58476d1ce4e5b9;
+      z = (z ^ (z >> 27)) * 0x94d049bb133111eb;
+      *word = z ^ (z >> 31);
+    }
+
+    Xoshiro256 { s: s }
+  }
+
+  fn next(&mut self) -> u64 {
+    let result = (self.s[1] * 5).rotate_left(7) * 9;
+    let t = self.s[1] << 17;
+
+    self.s[2] ^= self.s[0];
+    self.s[3] ^= self.s[1];
+    self.s[1] ^= self.s[2];
+    self.s[0] ^= self.s[3];
+    self.s[2] ^= t;
+    self.s[3] = self.s[3].rotate_left(45);
+
+    result
+  }
+}
+
+pub fn prefill_row_variant(num_rows: uint, variant: &AlgorithmVariant) -> Vec<u8> {
+  let state = prefill_state_variant(num_rows, variant);
+
+  // Return the desired row and throw away the first byte (t-values).
+  RowView(&state.rows[num_rows]).blocks().to_vec()
+}
+
+pub fn prefill_state(num_rows: uint) -> StateMatrix {
+  prefill_state_variant(num_rows, &AlgorithmVariant::FullMd2)
+}
+
+pub fn prefill_state_variant(num_rows: uint, variant: &AlgorithmVariant) -> StateMatrix {
+  prefill_state_with_sbox(num_rows, variant.sbox(), &variant.sboxi())
+}
+
+pub fn prefill_row_with_sbox(num_rows: uint, sbox: &[u8; 256], sboxi: &[u8; 256]) -> Vec<u8> {
+  let state = prefill_state_with_sbox(num_rows, sbox, sboxi);
+
+  // Return the desired row and throw away the first byte (t-values).
+  RowView(&state.rows[num_rows]).blocks().to_vec()
+}
+
+// The S-box must be a permutation and `sboxi` its inverse.
+pub fn prefill_state_with_sbox(num_rows: uint, sbox: &[u8; 256], sboxi: &[u8; 256]) -> StateMatrix {
+  // With 17 rows the last triangle pass would overwrite T1 and T2.
+  assert!(num_rows <= 16, "at most 16 rows can be prefilled, got {}", num_rows);
+
+  let mut state = [[0u8; 49]; 19];
+
+  for row in range_inclusive(1, num_rows) {
+    fill_t1(&mut state, row, sbox);
+  }
+
+  // Compute triangles in T2 and T3.
+  for col in range(0, num_rows) {
+    for row in range_inclusive(2 + col, num_rows).rev() {
+      fill_triangles(&mut state, row, col, sboxi);
+    }
+  }
+
+  StateMatrix { rows: state }
+}
+
+#[derive(Show, PartialEq)]
+pub enum ParseError {
+  WrongRowCount(uint),
+  WrongColumnCount(uint),
+  InvalidByte(String),
+  MalformedJson,
+  WrongByteCount(uint)
+}
+
+// Serializes the state matrix to 19 lines of 49 comma-separated decimal
+// byte values each, ready to be imported into a spreadsheet.
+pub fn to_csv(state: &StateMatrix) -> String {
+  let mut csv = String::new();
+
+  for row in state.rows.iter() {
+    let cols: Vec<String> = row.iter().map(|byte| byte.to_string()).collect();
+    csv.push_str(cols.connect(",")[]);
+    csv.push('\n');
+  }
+
+  csv
+}
+
+pub fn from_csv(s: &str) -> Result<StateMatrix, ParseError> {
+  let lines: Vec<&str> = s.lines().collect();
+  let mut state = StateMatrix::new_zeroed();
+
+  if lines.len() != 19 {
+    return Err(ParseError::WrongRowCount(lines.len()));
+  }
+
+  for (row, line) in lines.iter().enumerate() {
+    let cols: Vec<&str> = line.split(',').collect();
+
+    if cols.len() != 49 {
+      return Err(ParseError::WrongColumnCount(cols.len()));
+    }
+
+    for (col, field) in cols.iter().enumerate() {
+      match field.trim().parse::<u8>() {
+        Some(byte) => { state.rows[row][col] = byte; }
+        None => { return Err(ParseError::InvalidByte(field.to_string())); }
+      }
+    }
+  }
+
+  Ok(state)
+}
+
+// Serializes the state matrix as a JSON array of 19 arrays of 49 bytes,
+// formatted exactly like Python's `json.dumps()` would. The encoder doesn't
+// emit any whitespace, but as the output only holds numbers the separators
+// can simply be spaced out afterwards.
+pub fn state_to_json(state: &StateMatrix) -> String {
+  let rows: Vec<Vec<u8>> = state.rows.iter().map(|row| row.to_vec()).collect();
+  json::encode(&rows).replace(",", ", ")
+}
+
+// Parses arrays of arrays as written by `state_to_json`.
+pub fn state_from_json(s: &str) -> Result<StateMatrix, ParseError> {
+  let rows: Vec<Vec<u64>> = match json::decode(s) {
+    Ok(rows) => rows,
+    Err(_) => { return Err(ParseError::MalformedJson); }
+  };
+
+  if rows.len() != 19 {
+    return Err(ParseError::WrongRowCount(rows.len()));
+  }
+
+  let mut state = StateMatrix::new_zeroed();
+
+  for (row, cols) in rows.iter().enumerate() {
+    if cols.len() != 49 {
+      return Err(ParseError::WrongColumnCount(cols.len()));
+    }
+
+    for (col, &value) in cols.iter().enumerate() {
+      if value > 255 {
+        return Err(ParseError::InvalidByte(value.to_string()));
+      }
+
+      state.rows[row][col] = value as u8;
+    }
+  }
+
+  Ok(state)
+}
+
+// Entry [r][c] is the XOR of rows r+1 and r at column c. For every cell
+// computed by the MD2 cascade that equals the S-box applied to the cell
+// left of it, i.e. SBOX[state[r+1][c-1]].
+pub fn xor_profile(state: &StateMatrix) -> [[u8; 49]; 18] {
+  let mut profile = [[0u8; 49]; 18];
+
+  for row in range(0, 18) {
+    profile[row] = state.row_xor(row + 1, row);
+  }
+
+  profile
+}
+
+// Entry i is the Hamming weight of the XOR of rows i+1 and i, i.e. the
+// number of bits flipped by the S-box outputs applied in that round.
+pub fn row_weight_distribution(state: &StateMatrix) -> Vec<u32> {
+  state.consecutive_row_xors().iter().map(|xor| {
+    xor.iter().fold(0u32, |weight, byte| weight + byte.count_ones() as u32)
+  }).collect()
+}
+
+pub fn rotate_rows(state: &StateMatrix, shift: uint) -> StateMatrix {
+  let mut rotated = *state;
+
+  // Row 0 becomes row `shift`, and so on.
+  for row in range(0, 19) {
+    rotated.rows[(row + shift) % 19] = state.rows[row];
+  }
+
+  rotated
+}
+
+// Checks whether searching from row `16 - k` of the rotated state finds as
+// many collisions as the search on the original state. Rotating moves row
+// `16 - k - shift` (mod 19) of the original into that position, so this
+// compares the prefilled row against another row of the same matrix being
+// used as the starting state. Only shifts that are multiples of 19 are
+// guaranteed to be invariant, e.g. for k=2 a shift of 1 finds 109 instead
+// of 141 collisions.
+pub fn rotation_invariant(k: uint, shift: uint) -> Result<bool, Md2CollisionError> {
+  let num_rows = try!(row_for_k(k));
+  let state = prefill_state(num_rows);
+  let rotated = rotate_rows(&state, shift);
+
+  // Compare collision counts for the original and the rotated row.
+  Ok(try!(count_collisions(state.rows[num_rows][1..], num_rows)) ==
+     try!(count_collisions(rotated.rows[num_rows][1..], num_rows)))
+}
+
+pub fn sweep_rows(k: uint) -> Result<Vec<(uint, uint)>, Md2CollisionError> {
+  let max_row = try!(row_for_k(k));
+  let mut counts = vec!();
+
+  // Run a search over at most 2^16 candidates of k bytes for every row that
+  // leaves k free bytes and report the number of collisions found.
+  for row in range_inclusive(1u, max_row) {
+    let state = try!(prefill_row(row));
+    let candidates = try!(candidates_k(state[], row, k));
+    let collisions = try!(find_collisions(candidates.take(1 << 16)));
+
+    // Only count messages that really collide from the zero chaining value.
+    let count = collisions.iter().filter(|collision| {
+      validate_collision(*collision) == Ok(true)
+    }).fold(0u, |count, collision| count + collision.msgs.len() - 1);
+
+    counts.push((row, count));
+  }
+
+  Ok(counts)
+}
+
+pub fn count_collisions(state: &[u8], row: uint) -> Result<uint, Md2CollisionError> {
+  let mut map = HashMap::with_capacity(256u * 256u);
+
+  for (cmp, msg) in try!(candidates(state, row)) {
+    insert(&mut map, cmp, msg);
+  }
+
+  Ok(count(&map))
+}
+
+// Insert the given candidate pair, consisting of the compressed and the
+// original message, into the given hash map.
+fn insert(map: &mut HashMap<Vec<u8>,Vec<Vec<u8>>>, cmp: Vec<u8>, msg: Vec<u8>) {
+  match map.entry(cmp) {
+    Vacant(entry) => { entry.set(vec!(msg)); }
+    Occupied(mut entry) => { entry.get_mut().push(msg); }
+  }
+}
+
+// Count the number of map entries that have more than a single message.
+// Those will compress to the same final value and thus represent collisions.
+fn count(map: &HashMap<Vec<u8>,Vec<Vec<u8>>>) -> uint {
+  map.values().fold(0u, |count, msgs| count + msgs.len() - 1)
+}
+
+pub fn state_entropy(state: &[u8]) -> f64 {
+  let mut freqs = [0u; 256];
+
+  // Treat every byte as a sample and count byte value frequencies.
+  for byte in state.iter() {
+    freqs[*byte as uint] += 1;
+  }
+
+  // Shannon entropy in bits, ignoring values that never occur.
+  let len = state.len() as f64;
+  freqs.iter().filter(|&&n| n > 0).fold(0f64, |entropy, &n| {
+    let p = n as f64 / len;
+    entropy - p * p.log2()
+  })
+}
+
+// The MD2 S-box decomposes into cycles of length 3, 17, 23, 27, 42, and 144.
+// The first row of T1 in `prefill_row` is the S-box applied repeatedly to
+// zero, so it would only start repeating itself after 144 columns.
+pub fn element_order(sbox: &[u8; 256], start: u8) -> uint {
+  let mut x = sbox[start as uint];
+  let mut order = 1u;
+
+  // Follow the cycle until we're back at the start.
+  while x != start {
+    x = sbox[x as uint];
+    order += 1;
+  }
+
+  order
+}
+
+// Whether `sboxi` is the inverse of `sbox`, as `decompress` and the triangle
+// computation in `prefill_state_with_sbox` require.
+pub fn verify_sbox_inverse(sbox: &[u8; 256], sboxi: &[u8; 256]) -> bool {
+  range(0u, 256).all(|i| sboxi[sbox[i] as uint] == i as u8 && sbox[sboxi[i] as uint] == i as u8)
+}
+
+pub fn permutation_order(sbox: &[u8; 256]) -> uint {
+  // Maximum exponent of every prime factor over all cycle lengths.
+  let mut exps = [0u; 257];
+
+  for i in range(0u, 256) {
+    let mut len = element_order(sbox, i as u8);
+    let mut p = 2u;
+
+    // Factorize the cycle length.
+    while len > 1 {
+      let mut exp = 0u;
+
+      while len % p == 0 {
+        len /= p;
+        exp += 1;
+      }
+
+      exps[p] = cmp::max(exps[p], exp);
+      p += 1;
+    }
+  }
+
+  // The order is the LCM of all cycle lengths.
+  range(2u, 257).fold(1u, |order, p| order * p.pow(exps[p]))
+}
+
+// All bytes x with SBOX[x] == x ^ delta. The MD2 S-box has no fixed points
+// (delta=0) at all.
+pub fn find_near_fixed_points(delta: u8) -> Vec<u8> {
+  range(0u, 256).filter(|&x| SBOX[x] == x as u8 ^ delta).map(|x| x as u8).collect()
+}
+
+pub fn find_fixed_points() -> Vec<u8> {
+  find_near_fixed_points(0)
+}
+
+// The cycles of the S-box permutation, each starting at its smallest
+// element and ordered by that.
+pub fn sbox_cycle_decomposition() -> Vec<Vec<u8>> {
+  let mut seen = [false; 256];
+  let mut cycles = vec!();
+
+  for start in range(0u, 256) {
+    let mut cycle = vec!();
+    let mut x = start;
+
+    while !seen[x] {
+      seen[x] = true;
+      cycle.push(x as u8);
+      x = SBOX[x] as uint;
+    }
+
+    if !cycle.is_empty() {
+      cycles.push(cycle);
+    }
+  }
+
+  cycles
+}
+
+// Entry [i][j] is the fraction of inputs for which flipping input bit i
+// flips output bit j of the S-box. Ideally all entries are close to 0.5.
+pub fn sbox_avalanche_matrix() -> [[f32; 8]; 8] {
+  let mut matrix = [[0f32; 8]; 8];
+
+  for i in range(0u, 8) {
+    for input in range(0u, 256) {
+      let diff = SBOX[input] ^ SBOX[input ^ (1 << i)];
+
+      for j in range(0u, 8) {
+        if diff & (1 << j) > 0 {
+          matrix[i][j] += 1f32 / 256f32;
+        }
+      }
+    }
+  }
+
+  matrix
+}
+
+// Entry [din][dout] counts the inputs `a` for which SBOX[a] ^ SBOX[a ^ din]
+// equals dout. Every row sums up to 256.
+pub fn sbox_differential_distribution() -> [[u16; 256]; 256] {
+  let mut ddt = [[0u16; 256]; 256];
+
+  for din in range(0u, 256) {
+    for a in range(0u, 256) {
+      ddt[din][(SBOX[a] ^ SBOX[a ^ din]) as uint] += 1;
+    }
+  }
+
+  ddt
+}
+
+// The highest count in the DDT for a non-zero input difference. The lower,
+// the harder it is to predict how a difference propagates through the S-box.
+pub fn differential_uniformity() -> u16 {
+  let ddt = sbox_differential_distribution();
+
+  ddt[1..].iter().fold(0u16, |max, row| {
+    row.iter().fold(max, |max, &count| cmp::max(max, count))
+  })
+}
+
+pub fn predict_collision_count(row: uint) -> f64 {
+  // We know there are 141 collisions for k=2 (row=14) and 32784 for k=3
+  // (row=13). Fit a log-linear model through both and extrapolate.
+  let factor = 32784f64 / 141f64;
+  141f64 * factor.powf(14f64 - row as f64)
+}
+
+// The fitted number of collisions for k free bytes.
+pub fn expected_collisions(k: uint) -> f64 {
+  predict_collision_count(16 - k)
+}
+
+/// The smallest k for which at least one collision is found with a
+/// probability above `target_probability`, treating the number of collisions
+/// as Poisson distributed around `expected_collisions`.
+///
+/// A single free byte yields a collision less than half of the time:
+///
+/// ```
+/// extern crate "rust-md2-collisions" as collisions;
+///
+/// fn main() {
+///   assert_eq!(collisions::estimate_min_k(0.5), 2);
+/// }
+/// ```
+pub fn estimate_min_k(target_probability: f64) -> uint {
+  range_inclusive(1u, 16).find(|&k| {
+    1f64 - (-expected_collisions(k)).exp() > target_probability
+  }).unwrap_or(16)
+}
+
+pub fn predict_collision_interval(row: uint) -> (f64, f64) {
+  let expected = predict_collision_count(row);
+
+  // Collisions are rare events, approximate the Poisson distribution
+  // with a normal distribution to get a 95% confidence interval.
+  let delta = 1.96f64 * expected.sqrt();
+  ((expected - delta).max(0f64), expected + delta)
+}
+
+/// The number of collisions expected for 2^(8k) candidates if the compression
+/// function mapped them uniformly into its 128-bit output space.
+///
+/// That's far below what the prefilled MD2 state yields, e.g. 141 for k=2:
+///
+/// ```
+/// extern crate "rust-md2-collisions" as collisions;
+///
+/// fn main() {
+///   assert!(collisions::birthday_collision_count(2) < 1e-20f64);
+/// }
+/// ```
+pub fn birthday_collision_count(k: uint) -> f64 {
+  let candidates = 2f64.powi(8 * k as i32);
+  candidates * (candidates - 1f64) / 2f64.powi(129)
+}
+
+// How far the observed number of collisions is from `birthday_collision_count`,
+// relative to that prediction.
+pub fn collision_rate_deviation(observed: u64, k: uint) -> f64 {
+  let predicted = birthday_collision_count(k);
+  (observed as f64 - predicted) / predicted
+}
+
+// A single round of the MD2 compression function, `t_initial` is the value
+// of t at the start of the round.
+#[derive(Copy)]
+pub struct RowTransition {
+  pub row: uint,
+  pub t_initial: u8
+}
+
+impl RowTransition {
+  // The transition for the given row, following the given state.
+  pub fn after(state: &[u8; 48], row: uint) -> RowTransition {
+    RowTransition { row: row, t_initial: state[47] + (row as u8) - 1 }
+  }
+
+  pub fn apply(&self, state: &[u8; 48]) -> [u8; 48] {
+    let mut x = *state;
+    let mut t = self.t_initial;
+
+    for byte in x.iter_mut() {
+      *byte ^= SBOX[t as uint];
+      t = *byte;
+    }
+
+    x
+  }
+
+  // XOR'ing with the S-box output is its own inverse, we only need to start
+  // at the end of the row to know each byte's predecessor.
+  pub fn apply_inverse(&self, state: &[u8; 48]) -> [u8; 48] {
+    let mut x = *state;
+
+    for col in range(1u, 48).rev() {
+      x[col] ^= SBOX[x[col - 1] as uint];
+    }
+
+    x[0] ^= SBOX[self.t_initial as uint];
+    x
+  }
+}
+
+// Flips bit `initial_flip` of the all-zero 48-byte state and runs all 18
+// rounds on both states. Entry r is the number of bits of the first 16
+// bytes that differ after r rounds.
+pub fn diffusion_rounds(initial_flip: uint) -> Vec<u32> {
+  assert!(initial_flip < 384);
+
+  let mut original = [0u8; 48];
+  let mut flipped = [0u8; 48];
+  flipped[initial_flip / 8] ^= 1 << (initial_flip % 8);
+
+  let distance = |a: &[u8; 48], b: &[u8; 48]| {
+    range(0u, 16).fold(0u32, |distance, i| distance + (a[i] ^ b[i]).count_ones() as u32)
+  };
+
+  let mut weights = vec!(distance(&original, &flipped));
+
+  for row in range(0u, 18) {
+    // The first round starts with t = 0.
+    let round_original = if row == 0 {
+      RowTransition { row: 0, t_initial: 0 }
+    } else {
+      RowTransition::after(&original, row)
+    };
+
+    let round_flipped = if row == 0 {
+      RowTransition { row: 0, t_initial: 0 }
+    } else {
+      RowTransition::after(&flipped, row)
+    };
+
+    original = round_original.apply(&original);
+    flipped = round_flipped.apply(&flipped);
+    weights.push(distance(&original, &flipped));
+  }
+
+  weights
+}
+
+// The first round after which at least half of the 128 output bits differ.
+pub fn diffusion_threshold(initial_flip: uint) -> Option<uint> {
+  diffusion_rounds(initial_flip).iter().position(|&weight| weight >= 64)
+}
+
+// Yields the state after each of the remaining rounds of `compress`.
+pub struct CompressSteps {
+  x: Vec<u8>,
+  t: u8,
+  row: uint
+}
+
+impl Iterator<Vec<u8>> for CompressSteps {
+  fn next(&mut self) -> Option<Vec<u8>> {
+    if self.row >= 18 {
+      return None;
+    }
+
+    for byte in self.x.iter_mut() {
+      *byte ^= SBOX[self.t as uint];
+      self.t = *byte;
+    }
+
+    self.t += self.row as u8;
+    self.row += 1;
+    Some(self.x.clone())
+  }
+}
+
+pub fn compress_steps(state: &[u8], row: uint) -> CompressSteps {
+  CompressSteps { x: state.to_vec(), t: state[47] + row as u8 - 1, row: row }
+}
+
+// All states yielded by `compress_steps`, the first 16 bytes of the last
+// one are the compressed value.
+pub fn compress_trace(state: &[u8], start_row: uint) -> Vec<[u8; 48]> {
+  compress_steps(state, start_row).map(|x| {
+    let mut snapshot = [0u8; 48];
+    copy_memory(&mut snapshot, x[]);
+    snapshot
+  }).collect()
+}
+
+// Yields the state after each round undone by `decompress`.
+pub struct DecompressSteps {
+  x: Vec<u8>,
+  row: uint
+}
+
+impl Iterator<Vec<u8>> for DecompressSteps {
+  fn next(&mut self) -> Option<Vec<u8>> {
+    if self.row == 0 {
+      return None;
+    }
+
+    self.row -= 1;
+
+    for col in range(1, 48).rev() {
+      self.x[col] ^= SBOX[self.x[col - 1] as uint];
+    }
+
+    let t = self.x[47] + (self.row as u8) - 1;
+    self.x[0] ^= SBOX[t as uint];
+    Some(self.x.clone())
+  }
+}
+
+pub fn decompress_steps(state: &[u8], row: uint) -> DecompressSteps {
+  DecompressSteps { x: state.to_vec(), row: row }
+}
+
+fn compress(state: &[u8], iteration: uint, variant: &AlgorithmVariant) -> Result<Vec<u8>, Md2CollisionError> {
+  try!(check_state(state));
+
+  let sbox = variant.sbox();
+  let mut t = state[47] + iteration as u8 - 1;
+  let mut x = state.to_vec();
+
+  // Compute the MD2 compression function from the current state until we
+  // have the final compression state that would be fed into the next round.
+  for row in range(iteration, variant.rounds()) {
+    for byte in x.iter_mut() {
+      *byte ^= sbox[t as uint];
+      t = *byte;
+    }
+    t += row as u8;
+  }
+
+  Ok(x[..16].to_vec())
+}
+
+fn decompress(state: &[u8], iteration: uint, variant: &AlgorithmVariant) -> Result<Vec<u8>, Md2CollisionError> {
+  try!(check_state(state));
+
+  let sbox = variant.sbox();
+  let mut x = state.to_vec();
+
+  // Compute the MD2 compression function from the current state backwards
+  // until we arrive at the original message that needs to be passed into it
+  // to result in the current state.
+  for row in range(0, iteration).rev() {
+    for col in range(1, 48).rev() {
+      x[col] ^= sbox[x[col - 1] as uint];
+    }
+
+    let t = if row == 0 { 0 } else { x[47] + (row as u8) - 1 };
+    x[0] ^= sbox[t as uint];
+  }
+
+  // Every round can be inverted, running them forwards again must arrive
+  // at the given state.
+  debug_assert!(forward_rounds(x[], iteration, variant)[] == state);
+
+  Ok(x[16..32].to_vec())
+}
+
+// Runs the first `rounds` rounds of the compression function on the given
+// initial state.
+fn forward_rounds(initial: &[u8], rounds: uint, variant: &AlgorithmVariant) -> Vec<u8> {
+  let sbox = variant.sbox();
+  let mut x = initial.to_vec();
+  let mut t = 0u8;
+
+  for row in range(0, rounds) {
+    for byte in x.iter_mut() {
+      *byte ^= sbox[t as uint];
+      t = *byte;
+    }
+    t += row as u8;
+  }
+
+  x
+}
+
+// Like `decompress` but checks that compressing the returned message, from
+// the all-zero chaining value, actually arrives at the given state. That's
+// only the case for states built by `prefill_row` and `candidates`.
+pub fn decompress_checked(state: &[u8], row: uint) -> Result<Vec<u8>, Md2CollisionError> {
+  let variant = AlgorithmVariant::FullMd2;
+  let msg = try!(decompress(state, row, &variant));
+
+  if !leads_to_state(msg[], state, row, &variant) {
+    return Err(Md2CollisionError::InconsistentState { row: row });
+  }
+
+  Ok(msg)
+}
+
+// Whether the first `row` rounds of the compression function, starting
+// with the all-zero chaining value and `msg`, yield `state`.
+fn leads_to_state(msg: &[u8], state: &[u8], row: uint, variant: &AlgorithmVariant) -> bool {
+  let initial = create_initial_state(&[0u8; 16], msg);
+  forward_rounds(RowView(&initial).blocks(), row, variant)[] == state
+}
+
+// The first row of the state matrix for the given chaining value and
+// message. T3 is the chaining value XOR'ed with the message, t starts at 0.
+fn create_initial_state(iv: &[u8], msg: &[u8]) -> [u8; 49] {
+  let mut state = [0u8; 49];
+
+  {
+    let mut row = Row(&mut state);
+    copy_memory(row.t1_mut(), iv);
+    copy_memory(row.t2_mut(), msg);
+
+    for (byte, (a, b)) in row.t3_mut().iter_mut().zip(iv.iter().zip(msg.iter())) {
+      *byte = *a ^ *b;
+    }
+  }
+
+  state
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+  let mut hex = String::new();
+
+  for byte in bytes.iter() {
+    hex.push_str(format!("{:02x}", *byte)[]);
+  }
+
+  hex
+}
+
+// Formats both messages as hex, one per line. Bytes on which the messages
+// agree are coloured green, all others red, using ANSI escape codes.
+pub fn colored_hex_dump(msg1: &[u8], msg2: &[u8]) -> String {
+  format!("{}\n{}", to_colored_hex(msg1, msg2), to_colored_hex(msg2, msg1))
+}
+
+fn to_colored_hex(msg: &[u8], other: &[u8]) -> String {
+  let mut hex = String::new();
+
+  for (byte, other) in msg.iter().zip(other.iter()) {
+    let color = if byte == other { 32u } else { 31u };
+    hex.push_str(format!("\x1b[{}m{:02x}\x1b[0m", color, *byte)[]);
+  }
+
+  hex
+}
+
+// Parses hex digits into bytes, whitespace is ignored.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+  let digits: Option<Vec<uint>> = hex.chars()
+    .filter(|c| !c.is_whitespace())
+    .map(|c| c.to_digit(16))
+    .collect();
+
+  digits.and_then(|digits| {
+    if digits.len() % 2 != 0 {
+      return None;
+    }
+
+    Some(digits.chunks(2).map(|pair| (pair[0] * 16 + pair[1]) as u8).collect())
+  })
+}
+
+// Concatenates every message of the first block with every message of the
+// second one. All results collide if the second block's messages collide
+// when starting from the first block's compressed value.
+pub fn chain_collisions(block1: &Collision, block2: &Collision) -> Vec<Vec<u8>> {
+  let mut chained = vec!();
+
+  for msg1 in block1.msgs.iter() {
+    for msg2 in block2.msgs.iter() {
+      let mut msg = msg1.clone();
+      msg.push_all(msg2[]);
+      chained.push(msg);
+    }
+  }
+
+  chained
+}
+
+// Checks that all multi-block messages compress to the same value, chaining
+// the compression function from the all-zero chaining value.
+pub fn verify_chained(chained: &[Vec<u8>]) -> bool {
+  let cmps: Option<Vec<[u8; 16]>> = chained.iter().map(|msg| {
+    if msg.len() == 0 || msg.len() % 16 != 0 { None } else { Some(compress_blocks(msg[])) }
+  }).collect();
+
+  match cmps {
+    Some(ref cmps) if cmps.len() > 0 => cmps.iter().all(|cmp| cmp[] == cmps[0][]),
+    _ => false
+  }
+}
+
+// Compresses all 16-byte blocks of the message, starting from the all-zero
+// chaining value.
+fn compress_blocks(msg: &[u8]) -> [u8; 16] {
+  msg.chunks(16).fold([0u8; 16], |iv, chunk| {
+    let mut block = [0u8; 16];
+    copy_memory(&mut block, chunk);
+    compress_with_iv(&iv, &block)
+  })
+}
+
+// The MD2 checksum of a message that is already padded to full blocks, as
+// defined by RFC 1319 (with the errata applied).
+pub fn checksum_block(message: &[u8]) -> [u8; 16] {
+  assert!(message.len() % 16 == 0);
+
+  let mut checksum = [0u8; 16];
+  let mut l = 0u8;
+
+  for block in message.chunks(16) {
+    for j in range(0u, 16) {
+      checksum[j] ^= SBOX[(block[j] ^ l) as uint];
+      l = checksum[j];
+    }
+  }
+
+  checksum
+}
+
+// Checks whether two messages still collide after compressing their
+// checksum blocks. For single-block messages that never happens: the
+// checksum of a single block is a bijection, so different messages always
+// get different checksum blocks, and those collide with a probability of
+// about 2^-128 only.
+//
+// Appending the same suffix doesn't help either. For a fixed block the
+// checksum update is a bijection of the previous checksum, so different
+// checksums stay different. A full MD2 collision needs different suffix
+// blocks that collide again under the new chaining value and at the same
+// time cancel the checksum difference, i.e. a second-level search.
+pub fn collision_survives_checksum(m1: &[u8], m2: &[u8]) -> bool {
+  compress_with_iv(&compress_blocks(m1), &checksum_block(m1)) ==
+    compress_with_iv(&compress_blocks(m2), &checksum_block(m2))
+}
+
+// Formats 16 bytes like a UUID, in 8-4-4-4-12 groups of hex digits.
+pub fn compressed_as_uuid(cmp: &[u8; 16]) -> String {
+  format!("{}-{}-{}-{}-{}", to_hex(cmp[0..4]), to_hex(cmp[4..6]), to_hex(cmp[6..8]),
+          to_hex(cmp[8..10]), to_hex(cmp[10..16]))
+}
+
+// Two different "unique" IDs that compress to the same one.
+pub fn collision_as_uuids(msg1: &[u8], msg2: &[u8]) -> (String, String) {
+  let as_uuid = |msg: &[u8]| {
+    let mut bytes = [0u8; 16];
+    assert_eq!(msg.len(), 16);
+    copy_memory(&mut bytes, msg);
+    compressed_as_uuid(&bytes)
+  };
+
+  (as_uuid(msg1), as_uuid(msg2))
+}
+
+// Formats a collision as an RFC-style test vector, e.g.
+//
+//   k = 2
+//   msg1 = 00 01 02 ...
+//   msg2 = 10 11 12 ...
+//   compress(msg1) = compress(msg2) = 20 21 22 ...
+pub fn collision_to_rfc_vector(k: uint, msg1: &[u8], msg2: &[u8], cmp: &[u8]) -> String {
+  format!("k = {}\nmsg1 = {}\nmsg2 = {}\ncompress(msg1) = compress(msg2) = {}\n",
+          k, to_spaced_hex(msg1), to_spaced_hex(msg2), to_spaced_hex(cmp))
+}
+
+// Formats the first two messages of a collision as a Markdown table, one
+// row per byte offset.
+pub fn format_as_markdown_table(collision: &Collision) -> String {
+  let (msg1, msg2) = (&collision.msgs[0], &collision.msgs[1]);
+  let mut table = "| Byte offset | Msg 1 (hex) | Msg 2 (hex) | Same? |\n".to_string();
+  table.push_str("|---|---|---|---|\n");
+
+  for (offset, (a, b)) in msg1.iter().zip(msg2.iter()).enumerate() {
+    let same = if a == b { "✓" } else { "✗" };
+    table.push_str(format!("| {} | {:02x} | {:02x} | {} |\n", offset, *a, *b, same)[]);
+  }
+
+  table
+}
+
+// Formats the given bytes like Wireshark's "Hex + ASCII dump", e.g.
+//
+//   0000   47 45 54 20 2f 20 48 54 54 50 2f 31 2e 31 0d 0a   GET / HTTP/1.1..
+//
+// Non-printable bytes are shown as dots.
+pub fn wireshark_hex_dump(data: &[u8]) -> String {
+  let mut dump = String::new();
+
+  for (line, chunk) in data.chunks(16).enumerate() {
+    dump.push_str(format!("{:04x}  ", line * 16)[]);
+
+    for byte in chunk.iter() {
+      dump.push_str(format!(" {:02x}", *byte)[]);
+    }
+
+    // Pad short lines to keep the ASCII column aligned.
+    for _ in range(chunk.len(), 16) {
+      dump.push_str("   ");
+    }
+
+    dump.push_str("   ");
+
+    for &byte in chunk.iter() {
+      dump.push(if byte >= 0x20 && byte < 0x7f { byte as char } else { '.' });
+    }
+
+    dump.push('\n');
+  }
+
+  dump
+}
+
+// Dumps both messages, separated by a line of dashes.
+pub fn collision_wireshark_dump(msg1: &[u8], msg2: &[u8]) -> String {
+  let separator: String = repeat('-').take(73).collect();
+  format!("{}{}\n{}", wireshark_hex_dump(msg1), separator, wireshark_hex_dump(msg2))
+}
+
+fn to_spaced_hex(bytes: &[u8]) -> String {
+  let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", *byte)).collect();
+  hex.connect(" ")
+}
+
+pub fn recover_full_state(cmp: &[u8], msg: &[u8], row: uint) -> Vec<u8> {
+  let mut t = 0u8;
+
+  // Start from the all-zero chaining value.
+  let initial = create_initial_state(&[0u8; 16], msg);
+  let mut x = RowView(&initial).blocks().to_vec();
+
+  // Compute the MD2 compression function forwards until we arrive at the
+  // state that `decompress` would start from.
+  for i in range(0, row) {
+    for byte in x.iter_mut() {
+      *byte ^= SBOX[t as uint];
+      t = *byte;
+    }
+    t += i as u8;
+  }
+
+  debug_assert!(compress(x[], row, &AlgorithmVariant::FullMd2).unwrap().as_slice() == cmp);
+  x
+}
+
+// Runs the full MD2 compression function for the given chaining value and
+// message, as used when hashing multiple blocks.
+pub fn compress_with_iv(iv: &[u8; 16], message: &[u8; 16]) -> [u8; 16] {
+  let mut x = [0u8; 48];
+  let mut t = 0u8;
+
+  let initial = create_initial_state(iv, message);
+  copy_memory(&mut x, RowView(&initial).blocks());
+
+  for row in range(0, 18) {
+    for byte in x.iter_mut() {
+      *byte ^= SBOX[t as uint];
+      t = *byte;
+    }
+    t += row as u8;
+  }
+
+  let mut cmp = [0u8; 16];
+  copy_memory(&mut cmp, x[..16]);
+  cmp
+}
+
+pub mod validation {
+  use md2;
+  use std::cmp;
+  use std::collections::HashMap;
+  use std::os;
+  use std::sync::TaskPool;
+  use std::sync::mpsc::channel;
+  use util::constant_time_eq;
+
+  // Checks that all messages of every entry compress to its key, starting
+  // from the all-zero chaining value. Entries are split between one job per
+  // CPU, the result is known as soon as any job finds an invalid one.
+  pub fn validate_parallel(map: &HashMap<Vec<u8>,Vec<Vec<u8>>>) -> bool {
+    let num_threads = os::num_cpus();
+    let entries: Vec<(Vec<u8>, Vec<Vec<u8>>)> =
+      map.iter().map(|(cmp, msgs)| (cmp.clone(), msgs.clone())).collect();
+    let chunk_size = cmp::max(1, (entries.len() + num_threads - 1) / num_threads);
+
+    let pool = TaskPool::new(num_threads);
+    let (tx, rx) = channel();
+    let mut num_chunks = 0u;
+
+    for chunk in entries.chunks(chunk_size) {
+      let txc = tx.clone();
+      let chunk = chunk.to_vec();
+      num_chunks += 1;
+
+      pool.execute(move || {
+        let empty = [0u8; 16];
+        let valid = chunk.iter().all(|&(ref cmp, ref msgs)| {
+          msgs.iter().all(|msg| constant_time_eq(md2::compress(&empty, msg[])[], cmp[]))
+        });
+
+        // The receiver is gone if another job found an invalid entry.
+        let _ = txc.send(valid);
+      });
+    }
+
+    rx.iter().take(num_chunks).all(|valid| valid)
+  }
+}
+
+// Text formats to share collisions with other tools. Every line holds one
+// collision, the compressed value followed by all messages, separated by
+// " | ".
+pub mod export {
+  use serialize::base64::{ToBase64, STANDARD};
+  use serialize::json;
+  use {from_hex, to_hex, Collision, ParseError};
+
+  pub fn export_hex(collisions: &[Collision]) -> String {
+    export(collisions, |bytes: &[u8]| to_hex(bytes))
+  }
+
+  pub fn export_base64(collisions: &[Collision]) -> String {
+    export(collisions, |bytes: &[u8]| bytes.to_base64(STANDARD))
+  }
+
+  // A JSON array holding an object with the hex-encoded compressed value
+  // and messages for every collision.
+  pub fn export_json(collisions: &[Collision]) -> String {
+    let objects: Vec<HexCollision> = collisions.iter().map(|collision| {
+      HexCollision {
+        cmp: to_hex(collision.cmp[]),
+        msgs: collision.msgs.iter().map(|msg| to_hex(msg[])).collect()
+      }
+    }).collect();
+
+    json::encode(&objects)
+  }
+
+  #[derive(Encodable)]
+  struct HexCollision {
+    cmp: String,
+    msgs: Vec<String>
+  }
+
+  fn export<F: Fn(&[u8]) -> String>(collisions: &[Collision], encode: F) -> String {
+    let mut out = String::new();
+
+    for collision in collisions.iter() {
+      let mut fields = vec!(encode(collision.cmp[]));
+      fields.extend(collision.msgs.iter().map(|msg| encode(msg[])));
+      out.push_str(fields.connect(" | ")[]);
+      out.push('\n');
+    }
+
+    out
+  }
+
+  pub fn import_hex(s: &str) -> Result<Vec<Collision>, ParseError> {
+    let mut collisions = vec!();
+
+    for line in s.lines() {
+      let mut fields = vec!();
+
+      for field in line.split_str(" | ") {
+        match from_hex(field) {
+          Some(ref bytes) if bytes.len() == 16 => { fields.push(bytes.clone()); }
+          Some(bytes) => { return Err(ParseError::WrongByteCount(bytes.len())); }
+          None => { return Err(ParseError::InvalidByte(field.to_string())); }
+        }
+      }
+
+      // A collision needs at least two messages.
+      if fields.len() < 3 {
+        return Err(ParseError::WrongColumnCount(fields.len()));
+      }
+
+      collisions.push(Collision { cmp: fields[0].clone(), msgs: fields[1..].to_vec() });
+    }
+
+    Ok(collisions)
+  }
+}
+
+pub mod util {
+  // Compares two byte strings in time depending only on their lengths, not
+  // on the position of the first differing byte.
+  pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+      return false;
+    }
+
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+  }
+
+  // Returns a copy of `a` if `condition` is true and of `b` otherwise,
+  // without branching on `condition` per byte. Both must be of equal length.
+  pub fn constant_time_select(condition: bool, a: &[u8], b: &[u8]) -> Vec<u8> {
+    assert_eq!(a.len(), b.len());
+
+    let mask = if condition { 0xffu8 } else { 0u8 };
+    a.iter().zip(b.iter()).map(|(x, y)| (x & mask) | (y & !mask)).collect()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use birthday_collision_count;
+  use bit_difference;
+  use bit_difference_histogram;
+  use candidates;
+  use candidates_filtered;
+  use candidates_k;
+  use candidates_variant;
+  use candidates_with_progress;
+  use candidates_without_patterns;
+  use chain_collisions;
+  use checksum_block;
+  use collision_as_uuids;
+  use collision_iter;
+  use collision_rate_deviation;
+  use collision_survives_checksum;
+  use collision_to_rfc_vector;
+  use collision_wireshark_dump;
+  use colored_hex_dump;
+  use compress_steps;
+  use compress_trace;
+  use compress_with_iv;
+  use compressed_as_uuid;
+  use count;
+  use decompress_checked;
+  use decompress_steps;
+  use dedup_collisions;
+  use differential_uniformity;
+  use diffusion_rounds;
+  use diffusion_threshold;
+  use element_order;
+  use estimate_min_k;
+  use expected_collisions;
+  use find_all_k_up_to;
+  use find_collisions;
+  use find_collisions_bitset;
+  use find_collisions_masked;
+  use find_collisions_memory_limited;
+  use find_collisions_parallel;
+  use find_collisions_streaming;
+  use find_fixed_points;
+  use find_lowest_hamming_collision;
+  use find_multi_collisions;
+  use find_near_fixed_points;
+  use find_partial_collisions;
+  use find_second_preimage;
+  use format_as_markdown_table;
+  use from_csv;
+  use from_hex;
+  use infer_k_from_state;
+  use insert;
+  use k3_search_sharded;
+  use permutation_order;
+  use predict_collision_count;
+  use predict_collision_interval;
+  use prefill_row;
+  use prefill_row_cached;
+  use prefill_row_variant;
+  use prefill_row_verify;
+  use prefill_row_with_sbox;
+  use prefill_state;
+  use read_collisions;
+  use recover_full_state;
+  use rotate_rows;
+  use rotation_invariant;
+  use row_weight_distribution;
+  use run_parallel_with_callback;
+  use sbox_avalanche_matrix;
+  use sbox_cycle_decomposition;
+  use sbox_differential_distribution;
+  use state_entropy;
+  use state_from_json;
+  use state_to_json;
+  use stream_collisions;
+  use sweep_rows;
+  use to_csv;
+  use to_hex;
+  use validate_collision;
+  use verify_chained;
+  use verify_sbox_inverse;
+  use wireshark_hex_dump;
+  use write_collisions;
+  use xor_profile;
+  use export::{export_base64, export_hex, export_json, import_hex};
+  use util::{constant_time_eq, constant_time_select};
+  use validation::validate_parallel;
+  use AlgorithmVariant;
+  use AlphabetRange;
+  use ByteRange;
+  use CandidateSet;
+  use CandidatesDeduplicated;
+  use CheckpointedByteRange;
+  use Collision;
+  use CollisionGraph;
+  use CollisionRecord;
+  use CollisionSearchBuilder;
+  use CoverageTrackingCompressor;
+  use GlobalCollisionRegistry;
+  use IncrementalPrefill;
+  use LazyStateMatrix;
+  use Md2CollisionError;
+  use Md2State;
+  use ParseError;
+  use RowTransition;
+  use SboxCoverageMap;
+  use SearchState;
+  use ShuffledByteRange;
+  use StateMatrix;
+  use TrackedCandidates;
+  use ValidationError;
+  use VerboseCandidates;
+
+  use md2::{compress, SBOX, SBOXI};
+  use quickcheck::quickcheck;
+  use std::cell::Cell;
+  use std::collections::HashMap;
+  use std::io::{File, InvalidInput, TempDir};
+  use std::io::fs::readdir;
+  use std::iter::{order, range_inclusive, repeat};
+  use std::num::{Float, Int};
+  use std::slice::bytes::copy_memory;
+  use std::sync::{Arc, Mutex, TaskPool};
+  use std::sync::mpsc::channel;
+
+  // Validate all colliding entries in the given map to ensure that those
+  // messages do indeed collide when computing their compressed values.
+  fn validate(map: &HashMap<Vec<u8>,Vec<Vec<u8>>>) -> bool {
+    let empty = [0u8; 16];
+
+    // Ignore compressed values with only a single message (no collisions).
+    let collisions = map.iter().filter(|&(_, msgs)| msgs.len() > 1);
+
+    collisions.all(|(cmp, msgs)| {
+      msgs.iter().all(|msg| constant_time_eq(compress(&empty, msg[])[], cmp[]))
+    })
+  }
+
+  #[test]
+  fn test_k2() {
+    let state = prefill_row(14).unwrap();
+
+    // There will be ~2^16 entries (minus collisions).
+    let mut map = HashMap::with_capacity(256u * 256u);
+
+    // Iterate and record all candidate pairs.
+    for (cmp, msg) in candidates(state[], 14).unwrap() {
+      insert(&mut map, cmp, msg);
+    }
+
+    assert!(validate(&map));
+    assert_eq!(count(&map), 141);
+  }
+
+  #[test]
+  fn test_k3() {
+    let pool = TaskPool::new(8u);
+    let (tx, rx) = channel();
+    let state = prefill_row(13).unwrap();
+
+    for byte in range(0u, 256u) {
+      let txc = tx.clone();
+      let mut state = state.clone();
+
+      pool.execute(move || {
+        // Set the third bytes of T2 and T3.
+        state[18] = byte as u8;
+        state[34] = byte as u8;
+
+        for candidate in candidates(state[], 13).unwrap() {
+          if txc.send(candidate).is_err() {
+            panic!("sending failed");
+          }
+        }
+      });
+    }
+
+    // There will be ~2^24 entries (minus collisions).
+    let total = 256u * 256u * 256u;
+    let mut map = HashMap::with_capacity(total);
+
+    // Merge partial results.
+    for (key, msg) in rx.iter().take(total) {
+      insert(&mut map, key, msg);
+    }
+
+    assert!(validate(&map));
+    assert_eq!(count(&map), 32784);
+  }
+
+  #[test]
+  fn test_state_entropy() {
+    // 48 distinct bytes have the highest possible entropy.
+    let distinct: Vec<u8> = range(0u8, 48).collect();
+    assert!((state_entropy(distinct[]) - 48f64.log2()).abs() < 1e-9);
+
+    // Many bytes of the precomputed state are determined by the S-box
+    // cascade, the distribution is far from uniform.
+    assert!(state_entropy(prefill_row(14).unwrap()[]) < 4f64);
+  }
+
+  #[test]
+  fn test_identity_sbox() {
+    let variant = AlgorithmVariant::IdentitySbox;
+    let state = prefill_row_variant(14, &variant);
+    let mut map = HashMap::new();
+
+    // Without a non-linear S-box all candidates for k=2 trivially collide.
+    for (cmp, msg) in candidates_variant(state[], 14, variant).unwrap() {
+      insert(&mut map, cmp, msg);
+    }
+
+    assert_eq!(map.len(), 1);
+    assert_eq!(count(&map), 256u * 256u - 1);
+  }
+
+  #[test]
+  fn test_recover_full_state() {
+    let state = prefill_row(14).unwrap();
+
+    // Recovering the state from the first candidate yields the prefilled
+    // state with both zero-initialized bytes of T2 and T3.
+    for (cmp, msg) in candidates(state[], 14).unwrap().take(1) {
+      assert_eq!(recover_full_state(cmp[], msg[], 14), state);
+    }
+  }
+
+  #[test]
+  fn test_predict_collision_count() {
+    // The model is fit through the known counts for k=2 and k=3.
+    assert_eq!(predict_collision_count(14), 141f64);
+    assert!((predict_collision_count(13) - 32784f64).abs() < 1e-6);
+
+    // Every additional free byte yields more collisions.
+    assert!(predict_collision_count(12) > predict_collision_count(13));
+  }
+
+  #[test]
+  fn test_predict_collision_interval() {
+    // 141 +/- 1.96 * sqrt(141).
+    let (lower, upper) = predict_collision_interval(14);
+    assert!((lower - 117.726).abs() < 1e-3 && (upper - 164.274).abs() < 1e-3);
+    assert!(lower < 141f64 && 141f64 < upper);
+
+    // 32784 +/- 1.96 * sqrt(32784).
+    let (lower, upper) = predict_collision_interval(13);
+    assert!((lower - 32429.115).abs() < 1e-3 && (upper - 33138.885).abs() < 1e-3);
+    assert!(lower < 32784f64 && 32784f64 < upper);
+  }
+
+  #[test]
+  fn test_estimate_min_k() {
+    assert_eq!(expected_collisions(2), 141f64);
+    assert!((expected_collisions(3) - 32784f64).abs() < 1e-6);
+
+    // A single free byte is expected to yield less than one collision.
+    assert!(expected_collisions(1) < 1f64);
+    assert_eq!(estimate_min_k(0.4), 1);
+    assert_eq!(estimate_min_k(0.5), 2);
+    assert_eq!(estimate_min_k(0.999), 2);
+  }
+
+  #[test]
+  fn test_verbose_candidates() {
+    let state = prefill_row(14).unwrap();
+    let logged = Cell::new(0u);
+
+    // The logger is called once for every candidate yielded.
+    let verbose = VerboseCandidates::new(candidates(state[], 14).unwrap(), |_: &[u8], _: &[u8]| {
+      logged.set(logged.get() + 1);
+    });
+
+    assert_eq!(verbose.take(10).count(), 10);
+    assert_eq!(logged.get(), 10);
+
+    // The null logger doesn't change the candidates yielded.
+    let null = VerboseCandidates::null(candidates(state[], 14).unwrap());
+    assert!(order::equals(null.take(10), candidates(state[], 14).unwrap().take(10)));
+  }
+
+  #[test]
+  fn test_candidates_with_progress() {
+    let state = prefill_row(14).unwrap();
+    let mut reported = vec!();
+
+    {
+      let progress = candidates_with_progress(state[], 14, |count| reported.push(count)).unwrap();
+      assert_eq!(progress.every(10000).count(), 65536);
+    }
+
+    assert_eq!(reported, range(1u64, 7).map(|i| i * 10000).collect::<Vec<u64>>());
+
+    // By default, there's a single report for k=2.
+    let mut reported = 0u;
+    assert_eq!(candidates_with_progress(state[], 14, |_| reported += 1).unwrap().count(), 65536);
+    assert_eq!(reported, 1);
+  }
+
+  #[test]
+  fn test_rotate_rows() {
+    let state = prefill_state(14);
+    let rotated = rotate_rows(&state, 1);
+
+    // Every row moves down by one, the last row wraps around.
+    assert_eq!(rotated.rows[15][], state.rows[14][]);
+    assert_eq!(rotated.rows[0][], state.rows[18][]);
+
+    // Rotating by the number of rows is the identity.
+    let rotated = rotate_rows(&state, 19);
+    assert!(range(0, 19).all(|row| rotated.rows[row][] == state.rows[row][]));
+  }
+
+  #[test]
+  fn test_rotation_invariant() {
+    assert!(rotation_invariant(2, 0).unwrap());
+    assert!(rotation_invariant(2, 19).unwrap());
+
+    // Row 13 as the starting state only yields 109 collisions.
+    assert!(!rotation_invariant(2, 1).unwrap());
+  }
+
+  #[test]
+  fn test_sweep_rows() {
+    let counts = sweep_rows(2).unwrap();
+
+    // Rows 15 and 16 don't leave two free bytes.
+    assert_eq!(counts.len(), 14);
+    assert_eq!(counts[13], (14, 141));
+  }
+
+  #[test]
+  fn test_byte_range_wrap_around() {
+    let mut range = ByteRange::from_start(&[0xff, 0xfe]);
+
+    assert_eq!(range.next(), Some(vec!(0xff, 0xfe)));
+    assert_eq!(range.next(), Some(vec!(0xff, 0xff)));
+    assert_eq!(range.next(), None);
+
+    // Once done the range must not start over.
+    assert_eq!(range.next(), None);
+  }
+
+  #[test]
+  fn test_validate_rejects_invalid() {
+    let state = prefill_row(14).unwrap();
+    let mut map = HashMap::with_capacity(256u * 256u);
+
+    for (cmp, msg) in candidates(state[], 14).unwrap() {
+      insert(&mut map, cmp, msg);
+    }
+
+    // Flip a single bit in one of the messages of a valid collision.
+    let (cmp, msgs) = map.into_iter().find(|&(_, ref msgs)| msgs.len() > 1).unwrap();
+    let mut modified = msgs.clone();
+    modified[1][0] ^= 1;
+
+    let mut invalid = HashMap::new();
+    invalid.insert(cmp, modified);
+    assert!(!validate(&invalid));
+
+    // Two arbitrary messages that don't collide.
+    let msgs: Vec<Vec<u8>> = vec!(range(0u8, 16).collect(), range(16u8, 32).collect());
+    let cmp = compress(&[0u8; 16], msgs[0][]);
+
+    let mut invalid = HashMap::new();
+    invalid.insert(cmp, msgs);
+    assert!(!validate(&invalid));
+  }
+
+  #[test]
+  fn test_permutation_order() {
+    let mut identity = [0u8; 256];
+    for i in range(0u, 256) {
+      identity[i] = i as u8;
+    }
+
+    assert_eq!(element_order(&identity, 42), 1);
+    assert_eq!(permutation_order(&identity), 1);
+
+    // The MD2 S-box has no fixed points, zero is in the longest cycle.
+    assert_eq!(element_order(&SBOX, 0), 144);
+    assert_eq!(permutation_order(&SBOX), 1182384);
+  }
+
+  #[test]
+  fn test_sbox_inverse() {
+    for i in range(0u, 256) {
+      assert_eq!(SBOXI[SBOX[i] as uint], i as u8);
+    }
+
+    assert!(verify_sbox_inverse(&SBOX, &SBOXI));
+    assert!(!verify_sbox_inverse(&SBOX, &SBOX));
+  }
+
+  #[test]
+  fn test_sbox_fixed_points_and_cycles() {
+    assert!(find_fixed_points().is_empty());
+    assert_eq!(find_near_fixed_points(1), vec!(134, 162));
+
+    // The cycles partition all 256 byte values.
+    let cycles = sbox_cycle_decomposition();
+    let mut bytes: Vec<u8> = cycles.iter().flat_map(|cycle| cycle.iter().map(|&x| x)).collect();
+    bytes.sort();
+    assert_eq!(bytes, range(0u, 256).map(|x| x as u8).collect::<Vec<u8>>());
+
+    let mut lengths: Vec<uint> = cycles.iter().map(|cycle| cycle.len()).collect();
+    lengths.sort();
+    assert_eq!(lengths, vec!(3, 17, 23, 27, 42, 144));
+    assert!(cycles.iter().all(|cycle| cycle.len() == element_order(&SBOX, cycle[0])));
+  }
+
+  #[test]
+  fn test_candidate_set() {
+    let state = prefill_row(14).unwrap();
+    let mut set = CandidateSet::new();
+
+    for (cmp, msg) in candidates(state[], 14).unwrap() {
+      assert!(set.insert(cmp[], msg[]));
+    }
+
+    // All signatures for k=2 are distinct.
+    assert_eq!(set.len(), 256u * 256u);
+
+    for (cmp, msg) in candidates(state[], 14).unwrap().take(10) {
+      assert!(set.contains(cmp[], msg[]));
+      assert!(!set.insert(cmp[], msg[]));
+    }
+  }
+
+  #[test]
+  fn test_csv_roundtrip() {
+    let state = prefill_state(14);
+    let csv = to_csv(&state);
+    let parsed = from_csv(csv[]).unwrap();
+    assert!(range(0, 19).all(|row| parsed.rows[row][] == state.rows[row][]));
+
+    // Missing rows, columns, or invalid values are rejected.
+    let lines: Vec<&str> = csv.lines().collect();
+    let err = from_csv(lines[1..].connect("\n")[]).err();
+    assert_eq!(err, Some(ParseError::WrongRowCount(18)));
+
+    let err = from_csv(csv.replace("\n", ",0\n")[]).err();
+    assert_eq!(err, Some(ParseError::WrongColumnCount(50)));
+
+    let err = from_csv(csv.replace("0,", "x,")[]).err();
+    assert_eq!(err, Some(ParseError::InvalidByte("x".to_string())));
+  }
+
+  #[test]
+  fn test_candidates_exhaustiveness() {
+    // Every 2-byte seed is yielded by the range exactly once.
+    fn prop(a: u8, b: u8) -> bool {
+      let seed = vec!(a, b);
+      ByteRange::new(2).filter(|bytes| *bytes == seed).count() == 1
+    }
+
+    quickcheck(prop as fn(u8, u8) -> bool);
+  }
+
+  #[test]
+  fn test_md2_state() {
+    let bytes = prefill_row(14).unwrap();
+    let state = Md2State::from_slice(bytes[]).unwrap();
+
+    // The last byte of T1 is repeated at the end of T2 and T3.
+    assert_eq!(state.t1[15], state.t2[15]);
+    assert_eq!(state.t1[15], state.t3[15]);
+    assert_eq!(state.as_bytes()[], bytes[]);
+    assert_eq!(Md2State::from_bytes(&state.as_bytes()), state);
+
+    assert_eq!(Md2State::from_slice(bytes[..47]), None);
+  }
+
+  #[test]
+  fn test_state_matrix_constructors() {
+    let zeroed = StateMatrix::new_zeroed();
+    assert!(zeroed.rows.iter().all(|row| row.iter().all(|&byte| byte == 0)));
+
+    // Random matrices are deterministic for a given seed.
+    let random = StateMatrix::new_from_random(42);
+    assert_eq!(random.rows[0][..4], [21u8, 97, 174, 236][]);
+    assert_eq!(random.rows[18][], StateMatrix::new_from_random(42).rows[18][]);
+    assert!(random.rows[18][] != StateMatrix::new_from_random(43).rows[18][]);
+  }
+
+  #[test]
+  fn test_decompress_k3() {
+    let mut state = prefill_row(13).unwrap();
+
+    // Set the third bytes of T2 and T3.
+    state[18] = 0x2a;
+    state[34] = 0x2a;
+
+    // Compressing the original message from the all-zero chaining value
+    // must yield the same value as compressing from the current state.
+    for (cmp, msg) in candidates(state[], 13).unwrap().take(1000) {
+      assert_eq!(compress(&[0u8; 16], msg[]), cmp);
+    }
+  }
+
+  #[test]
+  fn test_collision_to_rfc_vector() {
+    let vector = collision_to_rfc_vector(2, &[0xab, 0xcd], &[0x12, 0x34], &[0x0f]);
+    assert_eq!(vector[], "k = 2\nmsg1 = AB CD\nmsg2 = 12 34\ncompress(msg1) = compress(msg2) = 0F\n");
+  }
+
+  #[test]
+  fn test_prefill_row_consistency() {
+    // Prefilling n rows extends the state for n-1 rows by one more row.
+    for n in range_inclusive(2u, 16) {
+      assert_eq!(prefill_row(n - 1).unwrap()[], prefill_state(n).rows[n - 1][1..]);
+    }
+  }
+
+  #[test]
+  fn test_xor_profile() {
+    let state = prefill_state(14);
+    let profile = xor_profile(&state);
+
+    let check = |&: row: uint, col: uint| {
+      SBOX[state.rows[row + 1][col - 1] as uint] == profile[row][col]
+    };
+
+    for row in range(0u, 14) {
+      // All of T1 is computed.
+      assert!(range(1u, 17).all(|col| check(row, col)));
+
+      // T2 and T3 only contain the precomputed triangles.
+      assert!(range(33 - row, 33).all(|col| check(row, col) && check(row, col + 16)));
+    }
+  }
+
+  #[test]
+  fn test_byte_range_position() {
+    let mut range = ByteRange::new(2);
+    assert_eq!(range.position(), 0);
+    assert_eq!(range.count_remaining(), 65536);
+
+    range.next();
+    assert_eq!(range.position(), 1);
+    assert_eq!(range.count_remaining(), 65535);
+
+    let mut range = ByteRange::from_start(&[0xff, 0xff]);
+    assert_eq!(range.position(), 65535);
+    assert_eq!(range.count_remaining(), 1);
+
+    range.next();
+    assert_eq!(range.count_remaining(), 0);
+
+    // The length doesn't depend on the position.
+    assert_eq!(range.len(), 65536);
+    assert_eq!(ByteRange::new(3).len(), 16777216);
+  }
+
+  #[test]
+  fn test_find_all_k_up_to() {
+    let results = find_all_k_up_to(2, 256u * 256u).unwrap();
+    assert_eq!(results.len(), 2);
+
+    // There are no collisions for k=1.
+    assert_eq!(results[0], (1, vec!()));
+
+    let (k, ref collisions) = results[1];
+    assert_eq!(k, 2);
+    assert_eq!(collisions.len(), 141);
+
+    // All messages compress to the same value.
+    assert!(collisions.iter().all(|collision| {
+      collision.msgs.iter().all(|msg| compress(&[0u8; 16], msg[]) == collision.cmp)
+    }));
+  }
+
+  #[test]
+  fn test_consecutive_row_xors() {
+    let state = prefill_state(14);
+    let xors = state.consecutive_row_xors();
+    assert_eq!(xors.len(), 18);
+    assert_eq!(xors[3][], state.row_xor(4, 3)[]);
+
+    // The first byte of T1 is XOR'ed with the S-box output for t.
+    assert_eq!(xors[0][1], SBOX[state.rows[1][0] as uint]);
+  }
+
+  #[test]
+  fn test_hex() {
+    assert_eq!(to_hex(&[0x00, 0xab, 0x12])[], "00ab12");
+    assert_eq!(from_hex("00ab12"), Some(vec!(0x00, 0xab, 0x12)));
+    assert_eq!(from_hex(" 00 AB\n12 "), Some(vec!(0x00, 0xab, 0x12)));
+
+    // Odd numbers of digits and non-hex characters are rejected.
+    assert_eq!(from_hex("00a"), None);
+    assert_eq!(from_hex("0g"), None);
+  }
+
+  #[test]
+  fn test_t2_equals_t3() {
+    for num_rows in range_inclusive(1u, 16) {
+      assert_eq!(prefill_state(num_rows).assert_t2_equals_t3(), Ok(()));
+    }
+
+    let mut state = prefill_state(14);
+    state.rows[5][20] ^= 1;
+    assert_eq!(state.assert_t2_equals_t3(), Err((5, 3)));
+  }
+
+  #[test]
+  fn test_format_as_markdown_table() {
+    let collision = Collision { cmp: vec!(0), msgs: vec!(vec!(0x01, 0xab), vec!(0x01, 0xcd)) };
+
+    assert_eq!(format_as_markdown_table(&collision)[],
+               "| Byte offset | Msg 1 (hex) | Msg 2 (hex) | Same? |\n\
+                |---|---|---|---|\n\
+                | 0 | 01 | 01 | ✓ |\n\
+                | 1 | ab | cd | ✗ |\n");
+  }
+
+  #[test]
+  fn test_validate_collision_length() {
+    let msg: Vec<u8> = range(0u8, 16).collect();
+    let collision = Collision { cmp: compress(&[0u8; 16], msg[]), msgs: vec!(msg.clone()) };
+    assert_eq!(validate_collision(&collision), Ok(true));
+
+    // Reject messages that aren't exactly 16 bytes.
+    for &len in [15u, 17].iter() {
+      let msgs = vec!(msg.clone(), range(0u8, len as u8).collect());
+      let collision = Collision { cmp: collision.cmp.clone(), msgs: msgs };
+      let err = ValidationError::WrongMessageLength { got: len };
+      assert_eq!(validate_collision(&collision), Err(err));
+    }
+  }
+
+  #[test]
+  fn test_find_partial_collisions() {
+    let state = prefill_row(14).unwrap();
+    let partial = find_partial_collisions(state[], 14, 2).unwrap();
+    assert!(count(&partial) >= 10 * 141);
+
+    // A full-length prefix is the regular search.
+    assert_eq!(count(&find_partial_collisions(state[], 14, 16).unwrap()), 141);
+  }
+
+  #[test]
+  fn test_find_collisions_bitset() {
+    let state = prefill_row(14).unwrap();
+    let collisions = find_collisions_bitset(state[], 14).unwrap();
+
+    // There are no false positives, the result matches a full search.
+    assert_eq!(collisions.len(), 141);
+    assert_eq!(collisions, find_collisions(candidates(state[], 14).unwrap()).unwrap());
+  }
+
+  #[test]
+  fn test_prefill_row_with_sbox() {
+    for num_rows in range_inclusive(1u, 16) {
+      assert_eq!(prefill_row_with_sbox(num_rows, &SBOX, &SBOXI), prefill_row(num_rows).unwrap());
+    }
+  }
+
+  #[test]
+  fn test_row_weight_distribution() {
+    let state = prefill_state(14);
+    let weights = row_weight_distribution(&state);
+    assert_eq!(weights.len(), 18);
+
+    // The first row is all zeros, so are all rows after row 15 that only
+    // holds the t-value for the next round.
+    let ones = state.rows[1].iter().fold(0u32, |ones, byte| ones + byte.count_ones() as u32);
+    assert_eq!(weights[0], ones);
+    assert!(weights[16..].iter().all(|&weight| weight == 0));
+  }
+
+  #[test]
+  fn test_k3_sharded() {
+    let collisions = k3_search_sharded(8);
+    let count = collisions.iter().fold(0u, |count, c| count + c.msgs.len() - 1);
+    assert_eq!(count, 32784);
+  }
+
+  #[test]
+  fn test_hamming_distance() {
+    let state = prefill_state(14);
+    assert_eq!(state.hamming_distance(&state), 0);
+
+    let mut other = state;
+    other.rows[7][7] ^= 0x81;
+    assert_eq!(state.hamming_distance(&other), 2);
+
+    // Zero and all-ones matrices differ in every bit.
+    let mut ones = StateMatrix::new_zeroed();
+    for row in ones.rows.iter_mut() {
+      for byte in row.iter_mut() {
+        *byte = 0xff;
+      }
+    }
+
+    assert_eq!(StateMatrix::new_zeroed().hamming_distance(&ones), 19 * 49 * 8);
+  }
+
+  #[test]
+  fn test_lazy_state_matrix() {
+    for num_rows in range_inclusive(1u, 16) {
+      let state = prefill_state(num_rows);
+      let mut lazy = LazyStateMatrix::new(num_rows);
+
+      for row in range(0u, 19) {
+        for col in range(0u, 49) {
+          assert_eq!(lazy.get(row, col), state.rows[row][col]);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_sbox_avalanche() {
+    let matrix = sbox_avalanche_matrix();
+
+    for row in matrix.iter() {
+      assert!(row.iter().all(|&p| p > 0.4 && p < 0.6));
+
+      // Flipping a single input bit flips about half of the output bits.
+      let flipped = row.iter().fold(0f32, |sum, &p| sum + p);
+      assert!(flipped > 3.5 && flipped < 4.5);
+    }
+  }
+
+  #[test]
+  fn test_sbox_differential_distribution() {
+    let ddt = sbox_differential_distribution();
+
+    // A zero input difference always yields a zero output difference.
+    assert_eq!(ddt[0][0], 256);
+
+    for row in ddt.iter() {
+      assert_eq!(row.iter().fold(0u, |sum, &count| sum + count as uint), 256);
+    }
+
+    assert_eq!(differential_uniformity(), 10);
+  }
+
+  #[test]
+  fn test_constant_time_eq() {
+    assert!(constant_time_eq(&[], &[]));
+    assert!(constant_time_eq(&[1, 2, 3], &[1, 2, 3]));
+    assert!(!constant_time_eq(&[1, 2, 3], &[1, 2, 4]));
+    assert!(!constant_time_eq(&[1, 2, 3], &[1, 2]));
+
+    assert_eq!(constant_time_select(true, &[1, 2], &[3, 4]), vec!(1, 2));
+    assert_eq!(constant_time_select(false, &[1, 2], &[3, 4]), vec!(3, 4));
+  }
+
+  #[test]
+  fn test_compress_with_iv() {
+    let mut iv = [0u8; 16];
+    let mut msg = [0u8; 16];
+
+    for i in range(0u, 16) {
+      iv[i] = (i * 17) as u8;
+      msg[i] = (255 - i) as u8;
+    }
+
+    assert_eq!(compress_with_iv(&iv, &msg)[], compress(&iv, &msg)[]);
+    assert_eq!(compress_with_iv(&[0u8; 16], &msg)[], compress(&[0u8; 16], &msg)[]);
+  }
+
+  #[test]
+  fn test_global_collision_registry() {
+    let dir = TempDir::new("registry").unwrap();
+    let path = dir.path().join("collisions");
+    let pool = TaskPool::new(2u);
+    let (tx, rx) = channel();
+
+    // Two writers register 100 collisions each, concurrently.
+    for writer in range(0u8, 2) {
+      let txc = tx.clone();
+      let registry = GlobalCollisionRegistry::new(path.clone());
+
+      pool.execute(move || {
+        for i in range(0u8, 100) {
+          let msgs = vec!(vec!(writer, i), vec!(i, writer));
+          let collision = Collision { cmp: vec!(writer), msgs: msgs };
+          registry.register_collision(&collision).unwrap();
+        }
+
+        if txc.send(()).is_err() {
+          panic!("sending failed");
+        }
+      });
+    }
+
+    // Wait for both writers.
+    for _ in rx.iter().take(2) {}
+
+    let registry = GlobalCollisionRegistry::new(path);
+    let collisions = registry.all_collisions().unwrap();
+    assert_eq!(collisions.len(), 200);
+
+    // No record was lost or corrupted.
+    for writer in range(0u8, 2) {
+      for i in range(0u8, 100) {
+        let msgs = vec!(vec!(writer, i), vec!(i, writer));
+        assert!(collisions.contains(&Collision { cmp: vec!(writer), msgs: msgs }));
+      }
+    }
+  }
+
+  #[test]
+  fn test_collision_records_roundtrip() {
+    let state = prefill_row(14).unwrap();
+    let collisions = find_collisions(candidates(state[], 14).unwrap()).unwrap();
+    let records: Vec<CollisionRecord> = collisions.into_iter().map(|collision| {
+      CollisionRecord { cmp: collision.cmp, msgs: collision.msgs, k: 2 }
+    }).collect();
+
+    let dir = TempDir::new("records").unwrap();
+    let path = dir.path().join("collisions.json");
+    write_collisions(&path, records[]).unwrap();
+
+    let read = read_collisions(&path).unwrap();
+    assert_eq!(read, records);
+
+    // The deserialized messages still collide.
+    let map: HashMap<Vec<u8>, Vec<Vec<u8>>> =
+      read.into_iter().map(|record| (record.cmp, record.msgs)).collect();
+    assert!(validate(&map));
+  }
+
+  #[test]
+  fn test_alphabet_range() {
+    let hex: Vec<Vec<u8>> = AlphabetRange::new(b"0123456789abcdef", 2).collect();
+    assert_eq!(hex.len(), 256);
+    assert_eq!(hex[0], b"00".to_vec());
+    assert_eq!(hex[17], b"11".to_vec());
+    assert_eq!(hex[255], b"ff".to_vec());
+
+    let bytes: Vec<Vec<u8>> = AlphabetRange::full_byte(2).collect();
+    assert_eq!(bytes, ByteRange::new(2).collect::<Vec<Vec<u8>>>());
+    assert_eq!(AlphabetRange::new(b"01", 3).count(), 8);
+  }
+
+  #[test]
+  fn test_byte_range_shuffled() {
+    let ordered: Vec<Vec<u8>> = ByteRange::new(2).collect();
+
+    for seed in range(0u64, 3) {
+      let mut shuffled: Vec<Vec<u8>> = ShuffledByteRange::new(2, seed).collect();
+      assert!(shuffled != ordered);
+
+      // Every sequence is visited exactly once.
+      shuffled.sort();
+      assert_eq!(shuffled, ordered);
+    }
+
+    // The same seed yields the same order, different seeds don't.
+    let a: Vec<Vec<u8>> = ShuffledByteRange::new(2, 1).collect();
+    let b: Vec<Vec<u8>> = ShuffledByteRange::new(2, 2).collect();
+    assert!(a == ShuffledByteRange::new(2, 1).collect::<Vec<Vec<u8>>>());
+    assert!(a != b);
+  }
+
+  #[test]
+  fn test_byte_range_indexed() {
+    let indexed = ByteRange::new(2).indexed();
+
+    for (i, (index, bytes)) in indexed.enumerate() {
+      assert_eq!(index, i as u64);
+      assert_eq!(bytes, vec!((i / 256) as u8, (i % 256) as u8));
+    }
+
+    assert_eq!(ByteRange::new(2).indexed().count(), 256u * 256u);
+  }
+
+  #[test]
+  fn test_find_collisions_masked() {
+    let state = prefill_row(14).unwrap();
+
+    // A full mask finds the same collisions as a regular search.
+    let collisions = find_collisions_masked(state[], 14, &[0xff; 16]).unwrap();
+    assert_eq!(collisions, find_collisions(candidates(state[], 14).unwrap()).unwrap());
+
+    // With only the first byte masked every value occurs many times.
+    let mut mask = [0u8; 16];
+    mask[0] = 0xff;
+
+    let collisions = find_collisions_masked(state[], 14, &mask).unwrap();
+    assert_eq!(collisions.len(), 256);
+    assert!(collisions.iter().all(|c| c.cmp[1..].iter().all(|&byte| byte == 0)));
+  }
+
+  #[test]
+  fn test_row_transition() {
+    let mut state = [0u8; 48];
+    copy_memory(&mut state, prefill_row(14).unwrap()[]);
+
+    // Composing all remaining rounds reproduces the compression function.
+    for (cmp, _) in candidates(state[], 14).unwrap().take(1) {
+      let mut x = state;
+
+      for row in range(14u, 18) {
+        let round = RowTransition::after(&x, row);
+        let next = round.apply(&x);
+        assert_eq!(round.apply_inverse(&next)[], x[]);
+        x = next;
+      }
+
+      assert_eq!(x[..16], cmp[]);
+    }
+  }
+
+  #[test]
+  fn test_incremental_prefill() {
+    let mut prefill = IncrementalPrefill::new();
+
+    for num_rows in range_inclusive(1u, 16) {
+      assert_eq!(prefill.extend_by_one(), prefill_row(num_rows).unwrap()[]);
+
+      let expected = prefill_state(num_rows);
+      for row in range(0u, 19) {
+        assert_eq!(prefill.state().rows[row][], expected.rows[row][]);
+      }
+    }
+  }
+
+  #[test]
+  fn test_byterange_is_bijection() {
+    let mut values: Vec<Vec<u8>> = ByteRange::new(2).collect();
+    assert_eq!(values.len(), 65536);
+
+    values.sort();
+    values.dedup();
+    assert_eq!(values.len(), 65536);
+
+    assert_eq!(values[0], vec!(0u8, 0));
+    assert_eq!(values[65535], vec!(255u8, 255));
+  }
+
+  #[test]
+  fn test_collision_search_builder() {
+    let found = Cell::new(0u);
+    let collisions = CollisionSearchBuilder::new().k(2).on_collision(|_: &Collision| {
+      found.set(found.get() + 1);
+    }).run().unwrap();
+
+    assert_eq!(collisions.len(), 141);
+    assert_eq!(found.get(), 141);
+    assert!(collisions.iter().all(|c| validate_collision(c) == Ok(true)));
+
+    // The sharded search finds the same compressed values.
+    let sharded = CollisionSearchBuilder::new().k(2).threads(4).run().unwrap();
+    assert!(order::equals(sharded.iter().map(|c| &c.cmp), collisions.iter().map(|c| &c.cmp)));
+  }
+
+  #[test]
+  fn test_colored_hex_dump() {
+    let dump = colored_hex_dump(&[0x00, 0x01], &[0x00, 0xff]);
+    assert_eq!(dump[], "\x1b[32m00\x1b[0m\x1b[31m01\x1b[0m\n\x1b[32m00\x1b[0m\x1b[31mff\x1b[0m");
+  }
+
+  #[test]
+  fn test_search_state_resume() {
+    let dir = TempDir::new("search-state").unwrap();
+    let path = dir.path().join("state");
+
+    // Interrupt a search after 1000 candidates.
+    let mut search = SearchState::new(14, 2).unwrap();
+    assert!(search.advance(1000));
+    search.save(&path).unwrap();
+
+    // Resume it from the saved state.
+    let mut resumed = SearchState::load(&path).unwrap();
+    assert_eq!(resumed.candidates().count(), 65536 - 1000);
+    while resumed.advance(1000) {}
+
+    let state = prefill_row(14).unwrap();
+    assert_eq!(resumed.collisions(), find_collisions(candidates(state[], 14).unwrap()).unwrap());
+  }
+
+  #[test]
+  fn test_checkpointed_byterange_resume() {
+    let dir = TempDir::new("checkpoint").unwrap();
+    let path = dir.path().join("range");
+
+    // Interrupt the enumeration right after the second checkpoint.
+    let range = CheckpointedByteRange::new(2, path.clone(), 50).unwrap();
+    let values = range.take(100).map(|bytes| bytes.unwrap());
+    assert!(order::equals(values, ByteRange::new(2).take(100)));
+
+    // Restarting continues with the next 100 values.
+    let range = CheckpointedByteRange::new(2, path.clone(), 50).unwrap();
+    let values = range.take(100).map(|bytes| bytes.unwrap());
+    assert!(order::equals(values, ByteRange::new(2).skip(100).take(100)));
+
+    // The checkpoint can't be resumed with a different number of bytes.
+    let err = CheckpointedByteRange::new(3, path.clone(), 50).err().unwrap();
+    assert_eq!(err.kind, InvalidInput);
+
+    // Failing to save a checkpoint is reported, not a panic.
+    let missing = dir.path().join("missing").join("range");
+    let mut range = CheckpointedByteRange::new(2, missing, 1).unwrap();
+    assert!(range.next().unwrap().is_err());
+  }
+
+  #[test]
+  fn test_multi_collisions() {
+    let state = prefill_row(13).unwrap();
+    let triples = find_multi_collisions(state[], 13, 3, 3).unwrap();
+
+    // For k=3 there are 40 triples and no larger groups.
+    assert_eq!(triples.len(), 40);
+    assert!(triples.iter().all(|collision| collision.msgs.len() == 3));
+    assert!(triples.iter().all(|collision| validate_collision(collision) == Ok(true)));
+  }
+
+  #[test]
+  fn test_diffusion_rounds() {
+    assert_eq!(diffusion_rounds(0), vec!(1u32, 59, 62, 67, 57, 66, 78, 73, 71, 61,
+                                         73, 69, 67, 75, 55, 65, 55, 57, 65));
+    assert_eq!(diffusion_threshold(0), Some(3));
+
+    // Bits outside of the first 16 bytes need two rounds to reach those.
+    assert_eq!(diffusion_rounds(128)[..2], [0u32, 0][]);
+    assert_eq!(diffusion_threshold(128), Some(2));
+  }
+
+  #[test]
+  fn test_byterange_partition() {
+    let full = ByteRange::new(2);
+    let parts = range(0u, 4).flat_map(|i| full.partition(4, i));
+    assert!(order::equals(parts, ByteRange::new(2)));
+
+    // Segments have equal size and can be split further.
+    assert_eq!(full.partition(4, 1).count_remaining(), 16384);
+    let part = full.partition(4, 3);
+    let halves = part.partition(2, 0).chain(part.partition(2, 1));
+    assert!(order::equals(halves, ByteRange::new(2).skip(49152)));
+
+    // More workers than sequences leave some of them without work.
+    let tiny = ByteRange::new(1).with_end(vec!(2u8));
+    assert_eq!(tiny.partition(4, 1).count(), 1);
+    assert_eq!(tiny.partition(4, 3).count(), 0);
+  }
+
+  #[test]
+  fn test_tracked_candidates() {
+    let state = prefill_row(14).unwrap();
+    let candidates = TrackedCandidates::new(candidates(state[], 14).unwrap());
+    let stats = candidates.statistics();
+
+    assert_eq!(find_collisions(candidates).unwrap().len(), 141);
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.candidates_evaluated, 65536);
+    assert_eq!(snapshot.collisions_found, 141);
+    assert_eq!(snapshot.bytes_enumerated, 2 * 65536);
+    assert_eq!(snapshot.collision_rate(), 141f64 / 65536f64);
+  }
+
+  #[test]
+  fn test_infer_k_from_state() {
+    for k in range_inclusive(1u, 16) {
+      assert_eq!(infer_k_from_state(prefill_row(16 - k).unwrap()[]), Some(k));
+    }
+
+    let mut state = prefill_row(14).unwrap();
+    state[16] = 1;
+    assert_eq!(infer_k_from_state(state[]), None);
+  }
+
+  #[test]
+  fn test_wireshark_hex_dump() {
+    let dump = wireshark_hex_dump(b"GET / HTTP/1.1\r\nHost");
+    assert_eq!(dump[], "0000   47 45 54 20 2f 20 48 54 54 50 2f 31 2e 31 0d 0a   GET / HTTP/1.1..\n\
+                        0010   48 6f 73 74                                       Host\n");
+
+    let dump = collision_wireshark_dump(&[0x41], &[0x00]);
+    let lines: Vec<&str> = dump[].lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "0000   41                                                A");
+    assert_eq!(lines[1].len(), lines[0].len() + 15);
+    assert_eq!(lines[2], "0000   00                                                .");
+  }
+
+  #[test]
+  fn test_second_preimage() {
+    let state = prefill_row(14).unwrap();
+    let collision = find_collisions(candidates(state[], 14).unwrap()).unwrap()[0].clone();
+
+    // The first matching candidate is the first message of the collision.
+    let msg = find_second_preimage(state[], 14, collision.cmp[]).unwrap();
+    assert_eq!(msg, Some(collision.msgs[0].clone()));
+
+    assert_eq!(find_second_preimage(state[], 14, &[0u8; 16]).unwrap(), None);
+  }
+
+  #[test]
+  fn test_decompress_checked() {
+    let mut state = prefill_row(14).unwrap();
+    let variant = AlgorithmVariant::FullMd2;
+    assert_eq!(decompress_checked(state[], 14), ::decompress(state[], 14, &variant));
+
+    // Changing a byte outside of the free ones breaks the state.
+    state[20] ^= 1;
+    let err = Md2CollisionError::InconsistentState { row: 14 };
+    assert_eq!(decompress_checked(state[], 14), Err(err));
+    let err = Md2CollisionError::InvalidStateLength { expected: 48, got: 47 };
+    assert_eq!(decompress_checked(state[..47], 14), Err(err));
+  }
+
+  #[test]
+  fn test_sbox_coverage() {
+    let mut state = prefill_row(14).unwrap();
+    let coverage = Arc::new(Mutex::new(SboxCoverageMap::new()));
+    let compressor = CoverageTrackingCompressor::new(coverage.clone());
+    let variant = AlgorithmVariant::FullMd2;
+
+    // Run through the same states as `candidates` for k=2.
+    for bytes in ByteRange::new(2) {
+      copy_memory(state.slice_mut(16, 18), bytes[]);
+      copy_memory(state.slice_mut(32, 34), bytes[]);
+      assert_eq!(compressor.compress(state[], 14), ::compress(state[], 14, &variant));
+    }
+
+    // Four rounds of 48 lookups for each candidate.
+    let coverage = coverage.lock().unwrap();
+    let total = coverage.counts.iter().fold(0u64, |sum, &count| sum + count);
+    assert_eq!(total, 65536 * 4 * 48);
+    assert!(coverage.coverage_fraction() > 0.9);
+  }
+
+  #[test]
+  fn test_compress_determinism() {
+    // Repeated calls on random states and rows yield the same results.
+    fn prop(state: Vec<u8>, row: u8) -> bool {
+      let state: Vec<u8> = state.into_iter().chain(repeat(0u8)).take(48).collect();
+      let row = row as uint % 18;
+      let variant = AlgorithmVariant::FullMd2;
+
+      ::compress(state[], row, &variant) == ::compress(state[], row, &variant) &&
+        ::decompress(state[], row, &variant) == ::decompress(state[], row, &variant)
+    }
+
+    quickcheck(prop as fn(Vec<u8>, u8) -> bool);
+  }
+
+  #[test]
+  fn test_candidates_wrong_state_length() {
+    let err = Md2CollisionError::InvalidStateLength { expected: 48, got: 47 };
+    assert_eq!(candidates(&[0u8; 47], 14).err(), Some(err));
+  }
+
+  #[test]
+  fn test_invalid_arguments() {
+    let state = prefill_row(14).unwrap();
+    let err = Md2CollisionError::InvalidRow { row: 17, max: 16 };
+    assert_eq!(candidates(state[], 17).err(), Some(err));
+    let err = Md2CollisionError::InvalidKParameter(2);
+    assert_eq!(candidates(state[], 15).err(), Some(err));
+    let err = Md2CollisionError::InvalidKParameter(3);
+    assert_eq!(candidates_k(state[], 14, 3).err(), Some(err));
+    assert!(candidates_k(state[], 14, 2).is_ok());
+
+    assert_eq!(prefill_row(17), Err(Md2CollisionError::InvalidRow { row: 17, max: 16 }));
+    assert_eq!(prefill_row(14), Ok(state));
+
+    // No rows are left for more than 16 free bytes.
+    let err = Md2CollisionError::InvalidKParameter(17);
+    assert_eq!(CollisionSearchBuilder::new().k(17).run().err(), Some(err));
+    assert_eq!(find_all_k_up_to(17, 1).err(), Some(err));
+    let mut out = vec!();
+    assert!(find_collisions_streaming(17, &mut out).is_err());
+    assert!(out.is_empty());
+    let err = Md2CollisionError::InvalidKParameter(0);
+    assert_eq!(CollisionSearchBuilder::new().k(0).run().err(), Some(err));
+
+    let dir = TempDir::new("md2-limited").unwrap();
+    assert!(find_collisions_memory_limited(state[], 16, 1024, dir.path()).is_err());
+  }
+
+  #[test]
+  fn test_candidates_deduplicated() {
+    let state = prefill_row(14).unwrap();
+    let unique = CandidatesDeduplicated::new(candidates(state[], 14).unwrap());
+
+    // Every collision removes one candidate from the image.
+    assert_eq!(unique.count(), 65536 - 141);
+  }
+
+  #[test]
+  fn test_collisions_valid_for_any_k() {
+    // Collisions found for the first candidates of any k are valid.
+    fn prop(k: u8) -> bool {
+      let k = k as uint % 6 + 1;
+      let row = 16 - k;
+      let state = prefill_row(row).unwrap();
+      let collisions = find_collisions(candidates_k(state[], row, k).unwrap().take(4096)).unwrap();
+
+      collisions.iter().all(|collision| validate_collision(collision) == Ok(true))
+    }
+
+    quickcheck(prop as fn(u8) -> bool);
+  }
+
+  #[test]
+  fn test_state_json_roundtrip() {
+    let state = prefill_state(14);
+    let json = state_to_json(&state);
+    assert!(json[].starts_with("[[0, 0, 0, "));
+
+    let parsed = state_from_json(json[]).unwrap();
+    for row in range(0u, 19) {
+      assert_eq!(parsed.rows[row][], state.rows[row][]);
+    }
+
+    assert_eq!(state_from_json("[[0, 1], [2]]").err(), Some(ParseError::WrongRowCount(2)));
+    assert_eq!(state_from_json("[0, 1]").err(), Some(ParseError::MalformedJson));
+    assert_eq!(state_from_json("[[0, [1]]]").err(), Some(ParseError::MalformedJson));
+  }
+
+  #[test]
+  fn test_collision_iter() {
+    let state = prefill_row(14).unwrap();
+    let pairs: Vec<Collision> = collision_iter(state[], 14).unwrap().collect();
+    assert_eq!(pairs.len(), 141);
+
+    // Every pair is part of a collision found by the full search.
+    let collisions = find_collisions(candidates(state[], 14).unwrap()).unwrap();
+    assert!(pairs.iter().all(|pair| {
+      collisions.iter().any(|c| c.cmp == pair.cmp && pair.msgs.iter().all(|msg| c.msgs.contains(msg)))
+    }));
+  }
+
+  #[test]
+  fn test_collision_graph() {
+    let state = prefill_row(14).unwrap();
+    let collisions = find_collisions(candidates(state[], 14).unwrap()).unwrap();
+    let graph = CollisionGraph::from_collisions(collisions[]);
+
+    // Every k=2 collision is a pair of messages.
+    assert_eq!(graph.num_nodes(), 2 * 141);
+    assert_eq!(graph.num_edges(), 141);
+    assert_eq!(graph.longest_chain().len(), 2);
+
+    // Overlapping collisions build longer chains.
+    let m = |byte: u8| vec!(byte);
+    let overlapping = [
+      Collision { cmp: vec!(0), msgs: vec!(m(1), m(2)) },
+      Collision { cmp: vec!(1), msgs: vec!(m(2), m(3), m(4)) }
+    ];
+
+    let graph = CollisionGraph::from_collisions(&overlapping);
+    assert_eq!(graph.num_edges(), 4);
+    assert_eq!(graph.longest_chain().len(), 4);
+    assert!(graph.longest_chain()[0] == m(1) || graph.longest_chain()[3] == m(1));
+  }
+
+  #[test]
+  fn test_find_collisions_streaming() {
+    let mut out = vec!();
+    assert_eq!(find_collisions_streaming(2, &mut out).unwrap(), 141);
+
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(out[].lines().count(), 141);
+
+    let state = prefill_row(14).unwrap();
+    let mut collisions = import_hex(out[]).unwrap();
+    let mut expected = find_collisions(candidates(state[], 14).unwrap()).unwrap();
+    dedup_collisions(&mut collisions);
+    dedup_collisions(&mut expected);
+    assert_eq!(collisions, expected);
+
+    // Splitting the search into passes finds the same collisions.
+    let mut out = vec!();
+    assert_eq!(stream_collisions(2, 4, &mut out).unwrap(), 141);
+    let mut collisions = import_hex(String::from_utf8(out).unwrap()[]).unwrap();
+    dedup_collisions(&mut collisions);
+    assert_eq!(collisions, expected);
+  }
+
+  #[test]
+  fn test_lowest_hamming_collision() {
+    let state = prefill_row(14).unwrap();
+    let (msg1, msg2, distance) = find_lowest_hamming_collision(state[], 14).unwrap();
+
+    // Even the closest messages differ in about a third of their bits.
+    assert_eq!(distance, 44);
+    assert_eq!(to_hex(msg1[]), "228d3edfb1065030f110154ae1ec9047");
+    assert_eq!(to_hex(msg2[]), "10952ef6f395ea3089b7772a40ccd20c");
+  }
+
+  #[test]
+  fn test_dedup_collisions() {
+    let collision = |cmp: u8, msgs: &[u8]| {
+      Collision { cmp: vec!(cmp), msgs: msgs.iter().map(|&msg| vec!(msg)).collect() }
+    };
+
+    let mut collisions = vec!(collision(2, &[1, 0]), collision(1, &[3, 4]),
+                              collision(2, &[0, 1]), collision(2, &[0, 1, 5]));
+    dedup_collisions(&mut collisions);
+
+    assert_eq!(collisions, vec!(collision(1, &[3, 4]), collision(2, &[0, 1]),
+                                collision(2, &[0, 1, 5])));
+  }
+
+  #[test]
+  fn test_collision_as_uuids() {
+    let cmp = [0x00u8, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77,
+               0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff];
+    assert_eq!(compressed_as_uuid(&cmp)[], "00112233-4455-6677-8899-aabbccddeeff");
+
+    let state = prefill_row(14).unwrap();
+    let collision = find_collisions(candidates(state[], 14).unwrap()).unwrap()[0].clone();
+    let (uuid1, uuid2) = collision_as_uuids(collision.msgs[0][], collision.msgs[1][]);
+    assert_eq!(uuid1.replace("-", ""), to_hex(collision.msgs[0][]));
+    assert_eq!(uuid2.replace("-", ""), to_hex(collision.msgs[1][]));
+  }
+
+  #[test]
+  fn test_state_matrix_show() {
+    let mut state = StateMatrix::new_zeroed();
+    state.rows[1][0] = 0xab;
+    state.rows[1][48] = 0xcd;
+
+    let grid = format!("{}", state);
+    let lines: Vec<&str> = grid[].lines().collect();
+    assert_eq!(lines.len(), 20);
+    assert!(lines[0].starts_with("   |  t | T1 "));
+    assert!(lines[2].starts_with(" 1 | AB | 00 00 "));
+    assert!(lines[2].ends_with(" | 00 00 CD"));
+    assert!(lines.iter().all(|line| line.len() == lines[0].len()));
+  }
+
+  #[test]
+  fn test_state_matrix_pretty_print() {
+    let state = prefill_state(14);
+    let plain = state.pretty_print(14, false);
+    let lines: Vec<&str> = plain[].lines().collect();
+    assert_eq!(lines.len(), 20);
+
+    // Every cell takes three characters after the row number and "t".
+    let cell = |row: uint, col: uint| {
+      let offset = 5 + 3 * col + 2 * ((col + 15) / 16);
+      lines[row + 1][offset + 2..offset + 3].to_string()
+    };
+
+    assert_eq!(cell(14, 17), ".");
+    assert_eq!(cell(14, 18), ".");
+    assert_eq!(cell(14, 33), ".");
+    assert_eq!(cell(14, 19), "T");
+    assert_eq!(cell(14, 16), "S");
+    assert_eq!(cell(15, 17), " ");
+    assert!(lines[15][].starts_with(format!("14 | {:02X}S| ", state.rows[14][0])[]));
+
+    let colored = state.pretty_print(14, true);
+    assert!(colored[].contains(format!("\x1b[33m{:02X}\x1b[0m", state.rows[14][17])[]));
+  }
+
+  #[test]
+  fn test_find_collisions_parallel() {
+    let state = prefill_row(14).unwrap();
+    let mut collisions = find_collisions_parallel(2, 4).unwrap();
+    let mut expected = find_collisions(candidates(state[], 14).unwrap()).unwrap();
+
+    // Messages might end up in a different order.
+    dedup_collisions(&mut collisions);
+    dedup_collisions(&mut expected);
+    assert_eq!(collisions, expected);
+
+    let collisions = find_collisions_parallel(3, 8).unwrap();
+    assert_eq!(collisions.iter().fold(0u, |count, c| count + c.msgs.len() - 1), 32784);
+  }
+
+  #[test]
+  fn test_run_parallel_with_callback() {
+    let mut found = vec!();
+
+    run_parallel_with_callback(2, 4, |collision: Collision| {
+      assert_eq!(collision.msgs.len(), 2);
+      assert_eq!(validate_collision(&collision), Ok(true));
+      found.push(collision.cmp);
+    }).unwrap();
+
+    // Every further message of a compressed value is reported on its own.
+    assert_eq!(found.len(), 141);
+
+    let err = Md2CollisionError::InvalidKParameter(8);
+    assert_eq!(run_parallel_with_callback(8, 4, |_: Collision| {}), Err(err));
+  }
+
+  #[test]
+  fn test_compress_rfc_vector() {
+    // The message "a", padded to a single block.
+    let mut block = [15u8; 16];
+    block[0] = b'a';
+
+    let mut state = [0u8; 48];
+    copy_memory(state.slice_mut(16, 32), &block);
+    copy_memory(state.slice_mut(32, 48), &block);
+
+    // `compress` picks up after the first round.
+    let state = RowTransition { row: 0, t_initial: 0 }.apply(&state);
+    let cmp = ::compress(state[], 1, &AlgorithmVariant::FullMd2);
+    assert_eq!(to_hex(cmp[]), "54d4da72c4c47da8c5caa1144e8e1683");
+
+    // Compressing the checksum block yields MD2("a") from RFC 1319.
+    let mut iv = [0u8; 16];
+    let mut checksum = [0u8; 16];
+    copy_memory(&mut iv, cmp[]);
+    copy_memory(&mut checksum, from_hex("19739cada3ba281693348e9d256fff31").unwrap()[]);
+    assert_eq!(to_hex(&compress_with_iv(&iv, &checksum)), "32ec01ec4a6dac72c0ab96fb34c0b5d1");
+  }
+
+  #[test]
+  fn test_validate_parallel() {
+    let mut map = HashMap::new();
+    let state = prefill_row(14).unwrap();
+
+    for (cmp, msg) in candidates(state[], 14).unwrap() {
+      insert(&mut map, cmp, msg);
+    }
+
+    assert!(validate_parallel(&map));
+
+    // A single wrong message is detected.
+    map.insert(vec!(0u8; 16), vec!(vec!(0u8; 16)));
+    assert!(!validate_parallel(&map));
+  }
+
+  #[test]
+  fn test_candidates_without_patterns() {
+    let state = prefill_row(14).unwrap();
+    let blocklist = [vec!(0u8), vec!(0xde, 0xad)];
+
+    let mut num_candidates = 0u;
+    for (_, msg) in candidates_without_patterns(state[], 14, &blocklist).unwrap() {
+      assert!(!msg.contains(&0u8));
+      assert!(!msg.windows(2).any(|window| window == [0xdeu8, 0xad][]));
+      num_candidates += 1;
+    }
+
+    // Exactly the other candidates are skipped.
+    let num_blocked = candidates(state[], 14).unwrap().filter(|&(_, ref msg)| {
+      msg.contains(&0u8) || msg.windows(2).any(|window| window == [0xdeu8, 0xad][])
+    }).count();
+    assert_eq!(num_candidates + num_blocked, 65536);
+  }
+
+  #[test]
+  fn test_prefill_row_verify() {
+    fn prop(num_rows: u8) -> bool {
+      prefill_row_verify(num_rows as uint % 16 + 1)
+    }
+
+    quickcheck(prop as fn(u8) -> bool);
+  }
+
+  #[test]
+  fn test_export_import() {
+    let state = prefill_row(14).unwrap();
+    let collisions = find_collisions(candidates(state[], 14).unwrap()).unwrap();
+
+    let hex = export_hex(collisions[]);
+    assert_eq!(hex[].lines().count(), 141);
+    assert_eq!(import_hex(hex[]), Ok(collisions.clone()));
+
+    let line = hex[].lines().next().unwrap().to_string();
+    assert_eq!(line.len(), 3 * 32 + 2 * 3);
+
+    let base64 = export_base64(collisions[..1]);
+    assert_eq!(base64[].split_str(" | ").count(), 3);
+    assert!(base64[].ends_with("==\n"));
+
+    let json = export_json(collisions[..2]);
+    let expected = format!("[{{\"cmp\":\"{}\",\"msgs\":[\"{}\",\"{}\"]}},",
+                           to_hex(collisions[0].cmp[]), to_hex(collisions[0].msgs[0][]),
+                           to_hex(collisions[0].msgs[1][]));
+    assert!(json[].starts_with(expected[]));
+    assert!(json[].ends_with("]}]"));
+    assert!(export_json(&[])[] == "[]");
+
+    let err = import_hex("00 | 01 | 02").err();
+    assert_eq!(err, Some(ParseError::WrongByteCount(1)));
+
+    let err = import_hex(line.replace(" | ", " | x")[]).err();
+    assert_eq!(err, Some(ParseError::InvalidByte("x".to_string() + line[35..67])));
+
+    let err = import_hex(line[..32 + 3 + 32]).err();
+    assert_eq!(err, Some(ParseError::WrongColumnCount(2)));
+  }
+
+  #[test]
+  fn test_prefill_row_cached() {
+    let dir = TempDir::new("prefill-cache").unwrap();
+    let path = dir.path().join("prefill_14.bin");
+
+    let row = prefill_row_cached(14, dir.path()).unwrap();
+    assert_eq!(row, prefill_row(14).unwrap());
+
+    let cached = File::open(&path).read_to_end().unwrap();
+    assert_eq!(cached[..4], b"MD2C"[]);
+    assert_eq!(cached[4], 1);
+    assert_eq!(cached[5..], row[]);
+
+    // The second call reads the file.
+    assert_eq!(prefill_row_cached(14, dir.path()).unwrap(), row);
+
+    // Stale caches are replaced.
+    File::create(&path).write(b"MD2C\x00").unwrap();
+    assert_eq!(prefill_row_cached(14, dir.path()).unwrap(), row);
+    assert_eq!(File::open(&path).read_to_end().unwrap(), cached);
+  }
+
+  #[test]
+  fn test_bit_difference() {
+    let diff = bit_difference(&[0x00, 0x03, 0x00, 0x01], &[0x00, 0x00, 0x00, 0x00]);
+    assert_eq!(diff.bits, vec!(8u, 9, 24));
+    assert_eq!(format!("{}", diff)[], "bits 8,9,24");
+
+    let state = prefill_row(14).unwrap();
+    let histogram = bit_difference_histogram(find_collisions(candidates(state[], 14).unwrap()).unwrap()[]);
+    assert_eq!(histogram.values().fold(0u64, |sum, count| sum + *count), 141);
+
+    // The closest pair differs in 44 bits, see `find_lowest_hamming_collision`.
+    assert_eq!(histogram.keys().min(), Some(&44));
+    assert_eq!(histogram.get(&44), Some(&1));
+  }
+
+  #[test]
+  fn test_candidates_filtered() {
+    let state = prefill_row(14).unwrap();
+    let filtered = candidates_filtered(state[], 14, |msg: &[u8]| msg[0] < 128).unwrap();
+
+    // Half of all messages pass the filter, so a random collision survives
+    // with a probability of 1/4, i.e. we'd expect about 35 of 141.
+    let mut map = HashMap::new();
+    let mut num_candidates = 0u;
+
+    for (cmp, msg) in filtered {
+      insert(&mut map, cmp, msg);
+      num_candidates += 1;
+    }
+
+    assert_eq!(num_candidates, 32768);
+    assert_eq!(count(&map), 39);
+  }
+
+  #[test]
+  fn test_chain_collisions() {
+    let state = prefill_row(14).unwrap();
+    let collisions = find_collisions(candidates(state[], 14).unwrap()).unwrap();
+
+    // Any block appended to colliding messages keeps them colliding.
+    let suffix = Collision { cmp: vec!(), msgs: vec!(vec!(0x42u8; 16)) };
+    let chained = chain_collisions(&collisions[0], &suffix);
+    assert_eq!(chained.len(), 2);
+    assert!(verify_chained(chained[]));
+
+    // Messages colliding from the all-zero chaining value don't collide
+    // anymore when starting from another one.
+    let chained = chain_collisions(&collisions[0], &collisions[1]);
+    assert_eq!(chained.len(), 4);
+    assert!(!verify_chained(chained[]));
+  }
+
+  #[test]
+  fn test_birthday_collision_count() {
+    // A random function would yield about 6.3e-30 collisions for k=2.
+    let predicted = birthday_collision_count(2);
+    assert!(predicted > 6.3e-30 && predicted < 6.4e-30);
+
+    // MD2 yields 141 collisions, far more than that.
+    assert!(collision_rate_deviation(141, 2) > 1e31);
+    assert_eq!(collision_rate_deviation(0, 2), -1f64);
+  }
+
+  #[test]
+  fn test_checksum_block() {
+    // The message "a", padded to a single block.
+    let mut block = [15u8; 16];
+    block[0] = b'a';
+
+    let checksum = checksum_block(&block);
+    assert_eq!(to_hex(&checksum), "19739cada3ba281693348e9d256fff31");
+
+    // No collision found for k=2 survives the checksum block.
+    let state = prefill_row(14).unwrap();
+    for collision in find_collisions(candidates(state[], 14).unwrap()).unwrap().iter() {
+      let (m1, m2) = (collision.msgs[0][], collision.msgs[1][]);
+      assert!(!collision_survives_checksum(m1, m2));
+    }
+  }
+
+  #[test]
+  fn test_compress_steps() {
+    let mut state = prefill_row(14).unwrap();
+    state[16] = 0x12;
+    state[32] = 0x12;
+
+    let variant = AlgorithmVariant::FullMd2;
+    let steps: Vec<Vec<u8>> = compress_steps(state[], 14).collect();
+    assert_eq!(steps.len(), 4);
+    assert_eq!(steps[3][..16], ::compress(state[], 14, &variant)[]);
+    assert!(steps.windows(2).all(|pair| pair[0] != pair[1]));
+
+    let steps: Vec<Vec<u8>> = decompress_steps(state[], 14).collect();
+    assert_eq!(steps.len(), 14);
+    assert_eq!(steps[13][16..32], ::decompress(state[], 14, &variant)[]);
+    assert!(steps.windows(2).all(|pair| pair[0] != pair[1]));
+  }
+
+  #[test]
+  fn test_compress_trace() {
+    let state = prefill_row(14).unwrap();
+    let trace = compress_trace(state[], 14);
+    assert_eq!(trace.len(), 4);
+
+    // Apply the 15th round by hand.
+    let mut x = state.clone();
+    let mut t = state[47] + 13;
+    for byte in x.iter_mut() {
+      *byte ^= SBOX[t as uint];
+      t = *byte;
+    }
+
+    assert_eq!(trace[0][], x[]);
+    assert_eq!(trace[3][..16], ::compress(state[], 14, &AlgorithmVariant::FullMd2)[]);
+  }
+
+  #[test]
+  fn test_find_collisions_memory_limited() {
+    let dir = TempDir::new("memory-limited").unwrap();
+    let state = prefill_row(14).unwrap();
+
+    // Spill to seven files of at most 10000 candidates each.
+    let limit = 32 * 10000;
+    let mut collisions = find_collisions_memory_limited(state[], 14, limit, dir.path()).unwrap();
+    let mut expected = find_collisions(candidates(state[], 14).unwrap()).unwrap();
+
+    dedup_collisions(&mut collisions);
+    dedup_collisions(&mut expected);
+    assert_eq!(collisions, expected);
+
+    // All chunk files were cleaned up.
+    assert!(readdir(dir.path()).unwrap().is_empty());
   }
 }
@@ -0,0 +1,175 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this file,
+ * You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+#![feature(slicing_syntax)]
+
+extern crate "rust-md2-collisions" as collisions;
+
+use collisions::{candidates_k, colored_hex_dump, find_collisions, from_hex};
+use collisions::{predict_collision_count, prefill_row, to_hex, validate_collision};
+use collisions::{Collision, CollisionSearchBuilder};
+use collisions::export::{export_hex, export_json};
+use std::io::{File, stdin};
+use std::io::stdio::stdout_raw;
+use std::os;
+use std::time::Duration;
+
+static USAGE: &'static str = "Usage: rust-md2-collisions [--k <N>] [--threads <N>]
+                           [--output <hex|json|count>] [--verify | --no-verify] [--dry-run]
+       rust-md2-collisions --pipe [--k <N>] [--no-color]
+       rust-md2-collisions report [<path>]";
+
+enum Output {
+  Hex,
+  Json,
+  Count
+}
+
+fn main() {
+  let args = os::args();
+
+  if args.len() > 1 && args[1][] == "report" {
+    let path = if args.len() > 2 { args[2][] } else { "RESULTS.md" };
+    return report(path);
+  }
+
+  let mut args = args.iter().skip(1);
+  let mut k = 2u;
+  let mut threads = os::num_cpus();
+  let mut output = Output::Count;
+  let mut verify = true;
+  let mut dry_run = false;
+  let mut pipe = false;
+  let mut color = true;
+
+  while let Some(arg) = args.next() {
+    match arg[] {
+      "--k" => {
+        match args.next().and_then(|value| value[].parse::<uint>()) {
+          Some(value) if value > 0 && value <= 16 => { k = value; }
+          _ => { return usage(); }
+        }
+      }
+      "--threads" => {
+        match args.next().and_then(|value| value[].parse::<uint>()) {
+          Some(value) if value > 0 => { threads = value; }
+          _ => { return usage(); }
+        }
+      }
+      "--output" => {
+        match args.next().map(|value| value[]) {
+          Some("hex") => { output = Output::Hex; }
+          Some("json") => { output = Output::Json; }
+          Some("count") => { output = Output::Count; }
+          _ => { return usage(); }
+        }
+      }
+      "--verify" => { verify = true; }
+      "--no-verify" => { verify = false; }
+      "--dry-run" => { dry_run = true; }
+      "--pipe" => { pipe = true; }
+      "--no-color" => { color = false; }
+      _ => { return usage(); }
+    }
+  }
+
+  let row = 16 - k;
+
+  if dry_run {
+    return println!("Expecting about {:.0} collisions.", predict_collision_count(row));
+  }
+
+  if !pipe {
+    // `--k` was checked above.
+    let collisions = CollisionSearchBuilder::new().k(k).threads(threads).run().unwrap();
+
+    if verify && !collisions.iter().all(|c| validate_collision(c) == Ok(true)) {
+      os::set_exit_status(1);
+      return println!("Found invalid collisions.");
+    }
+
+    match output {
+      Output::Hex => { print!("{}", export_hex(collisions[])); }
+      Output::Json => { println!("{}", export_json(collisions[])); }
+      Output::Count => {
+        let count = collisions.iter().fold(0u, |count, c| count + c.msgs.len() - 1);
+        println!("Found {} collisions.", count);
+      }
+    }
+
+    return;
+  }
+
+  // Read the initial state as 48 hex-encoded bytes from stdin.
+  let state = match stdin().read_to_string().ok().and_then(|input| from_hex(input[])) {
+    Some(ref state) if state.len() == 48 => state.clone(),
+    _ => {
+      os::set_exit_status(1);
+      return println!("Expected 48 hex-encoded bytes on stdin.");
+    }
+  };
+
+  // Highlight differing bytes only when writing to a terminal.
+  let color = color && stdout_raw().isatty();
+
+  // The state has the right length and `--k` was checked above.
+  let collisions = find_collisions(candidates_k(state[], row, k).unwrap()).unwrap();
+
+  // Write every colliding pair of messages to stdout.
+  for collision in collisions.iter() {
+    for msg in collision.msgs[1..].iter() {
+      if color {
+        println!("{}\n", colored_hex_dump(collision.msgs[0][], msg[]));
+      } else {
+        println!("{} {}", to_hex(collision.msgs[0][]), to_hex(msg[]));
+      }
+    }
+  }
+}
+
+// Runs the k=2 search and writes a Markdown summary of the results.
+fn report(path: &str) {
+  let state = prefill_row(14).unwrap();
+  let mut collisions: Vec<Collision> = vec!();
+
+  let duration = Duration::span(|| {
+    collisions = find_collisions(candidates_k(state[], 14, 2).unwrap()).unwrap();
+  });
+
+  let mut md = String::new();
+  md.push_str("# MD2 collision search results\n\n");
+  md.push_str("## Parameters\n\n");
+  md.push_str("* k = 2 free bytes, 2^16 candidates\n");
+  md.push_str("* 14 prefilled rows\n");
+  md.push_str(format!("* {} CPUs\n\n", os::num_cpus())[]);
+
+  // Every compressed value with n messages yields n-1 collisions.
+  let count = collisions.iter().fold(0u, |count, c| count + c.msgs.len() - 1);
+
+  md.push_str("## Results\n\n");
+  md.push_str(format!("Found {} collisions in {} ms.\n\n",
+                      count, duration.num_milliseconds())[]);
+  md.push_str("| Message 1 | Message 2 | Compressed |\n");
+  md.push_str("|---|---|---|\n");
+
+  for collision in collisions.iter().take(10) {
+    md.push_str(format!("| {} | {} | {} |\n", to_hex(collision.msgs[0][]),
+                        to_hex(collision.msgs[1][]), to_hex(collision.cmp[]))[]);
+  }
+
+  md.push_str("\n## What this means\n\n");
+  md.push_str("Every pair of messages above compresses to the same value when\n\
+               starting from the all-zero chaining value. A random 128-bit\n\
+               function would be expected to yield no collisions at all for\n\
+               2^16 inputs. MD2's compression function is therefore far from\n\
+               collision resistant and MD2 must not be used anymore.\n");
+
+  File::create(&Path::new(path)).write_str(md[]).unwrap();
+  println!("Wrote {}.", path);
+}
+
+fn usage() {
+  os::set_exit_status(1);
+  println!("{}", USAGE);
+}
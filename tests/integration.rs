@@ -0,0 +1,14 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this file,
+ * You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::io::process::Command;
+
+#[test]
+fn test_main_finds_k2_collisions() {
+  let output = Command::new("cargo").args(&["run", "--bin", "rust-md2-collisions"])
+                                    .output().unwrap();
+
+  let stdout = String::from_utf8(output.output).unwrap();
+  assert!(stdout.contains("Found 141 collisions."));
+}
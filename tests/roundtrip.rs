@@ -0,0 +1,115 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this file,
+ * You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+#![feature(slicing_syntax)]
+
+extern crate "rust-md2" as md2;
+extern crate "rust-md2-collisions" as collisions;
+extern crate quickcheck;
+
+use collisions::{candidates, compress_with_iv, decompress_checked, recover_full_state};
+use collisions::RowTransition;
+use md2::SBOX;
+use quickcheck::QuickCheck;
+use std::iter::repeat;
+use std::slice::bytes::copy_memory;
+
+// Pads or truncates random input to the given number of bytes.
+fn bytes(input: &[u8], len: uint) -> Vec<u8> {
+  input.iter().map(|&b| b).chain(repeat(0u8)).take(len).collect()
+}
+
+// Running all 18 rounds on the initial state for any chaining value and
+// message yields the compressed value computed by `md2::compress`, and
+// running them backwards again restores the initial state.
+#[test]
+fn test_rounds_roundtrip() {
+  fn prop(iv: Vec<u8>, msg: Vec<u8>) -> bool {
+    let mut original = [0u8; 48];
+    let mut chaining = [0u8; 16];
+    copy_memory(&mut chaining, bytes(iv[], 16)[]);
+
+    // The chaining value, the message, and both XOR'ed.
+    for (i, byte) in bytes(msg[], 16).into_iter().enumerate() {
+      original[i] = chaining[i];
+      original[16 + i] = byte;
+      original[32 + i] = chaining[i] ^ byte;
+    }
+
+    let mut x = original;
+    for r in range(0u, 18) {
+      let t = if r == 0 { 0 } else { x[47] + r as u8 - 1 };
+      x = RowTransition { row: r, t_initial: t }.apply(&x);
+    }
+
+    if x[..16] != md2::compress(&chaining, original[16..32])[] {
+      return false;
+    }
+
+    for r in range(0u, 18).rev() {
+      // The last byte of the previous row is the first one restored.
+      let last = x[47] ^ SBOX[x[46] as uint];
+      let t = if r == 0 { 0 } else { last + r as u8 - 1 };
+      x = RowTransition { row: r, t_initial: t }.apply_inverse(&x);
+    }
+
+    x[] == original[]
+  }
+
+  QuickCheck::new().tests(1000).quickcheck(prop as fn(Vec<u8>, Vec<u8>) -> bool);
+}
+
+// Running the rounds after `row` on the state recovered for a message
+// yields its compressed value.
+#[test]
+fn test_recovered_state_compresses() {
+  fn prop(msg: Vec<u8>, row: u8) -> bool {
+    let row = row as uint % 17 + 1;
+    let mut block = [0u8; 16];
+    copy_memory(&mut block, bytes(msg[], 16)[]);
+
+    let cmp = compress_with_iv(&[0u8; 16], &block);
+    let mut x = [0u8; 48];
+    copy_memory(&mut x, recover_full_state(&cmp, &block, row)[]);
+
+    for r in range(row, 18) {
+      x = RowTransition::after(&x, r).apply(&x);
+    }
+
+    x[..16] == cmp[]
+  }
+
+  QuickCheck::new().tests(1000).quickcheck(prop as fn(Vec<u8>, u8) -> bool);
+}
+
+// Decompressing the state recovered for a message yields that message.
+#[test]
+fn test_decompress_recovered_state() {
+  fn prop(msg: Vec<u8>, row: u8) -> bool {
+    let row = row as uint % 17 + 1;
+    let mut block = [0u8; 16];
+    copy_memory(&mut block, bytes(msg[], 16)[]);
+
+    let cmp = compress_with_iv(&[0u8; 16], &block);
+    let state = recover_full_state(&cmp, &block, row);
+    decompress_checked(state[], row) == Ok(block.to_vec())
+  }
+
+  QuickCheck::new().tests(1000).quickcheck(prop as fn(Vec<u8>, u8) -> bool);
+}
+
+// Candidates can be computed for any state and row in 1..=14, the rows
+// leaving two free bytes, even if the state isn't reachable from a
+// prefilled one.
+#[test]
+fn test_candidates_arbitrary_state() {
+  fn prop(state: Vec<u8>, row: u8) -> bool {
+    let row = row as uint % 14 + 1;
+    let state = bytes(state[], 48);
+
+    candidates(state[], row).unwrap().take(16).all(|(cmp, msg)| cmp.len() == 16 && msg.len() == 16)
+  }
+
+  QuickCheck::new().tests(1000).quickcheck(prop as fn(Vec<u8>, u8) -> bool);
+}